@@ -31,17 +31,46 @@ pub enum Commands {
     /// (Plumbing) Interactively ask for dotfile configurations
     Ask(AskArgs),
 
+    /// View and edit stored answers
+    Answers(AnswersArgs),
+
     /// (Plumbing) Render dotfile templates
-    Render,
+    Render {
+        /// Stop at the first template which fails instead of rendering all others
+        #[arg(long)]
+        fail_fast: bool,
+    },
 
     /// (Plumbing) Link files
-    Link,
+    Link {
+        /// Stop at the first file which fails instead of linking all others
+        #[arg(long)]
+        fail_fast: bool,
+    },
 
     /// (Plumbing) Run install scripts
-    Install,
+    Install(InstallArgs),
+
+    /// Re-render templates and re-link files whenever they change
+    Watch(WatchArgs),
+
+    /// Validate the config file and the variables used by templates
+    Check,
+
+    /// Update the config file to the current version of the config format
+    MigrateConfig(MigrateConfigArgs),
+
+    /// Inspect the effective settings
+    Config(ConfigArgs),
+
+    /// Fast-forward the dotfile repository and sync what the incoming commits changed
+    Pull(PullArgs),
+
+    /// Commit all changes of the dotfile repository, except files generated by sams
+    Commit(CommitArgs),
 
-    /// (Plumbing) Pull
-    Pull,
+    /// Push the dotfile repository, unless files generated by sams are tracked
+    Push,
 
     /// (Plumbing) Generate shell completions
     Completions {
@@ -61,9 +90,9 @@ pub struct GlobalArgs {
     #[clap(short, long = "config", default_value = "sams.toml")]
     pub config_path: PathBuf,
 
-    /// Root directory
-    #[clap(short, long, default_value = ".")]
-    pub root: PathBuf,
+    /// Root directory, by default the closest directory upwards containing the config file
+    #[clap(short, long)]
+    pub root: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, clap::Parser)]
@@ -71,16 +100,94 @@ pub struct SyncArgs {
     // Overwrite existing answers file
     #[clap(long)]
     pub ask: bool,
+
+    /// Only sync files below these paths (and tasks whose script is below them)
+    pub paths: Vec<PathBuf>,
+
+    /// Only run these phases
+    #[clap(long, value_delimiter = ',', conflicts_with = "skip")]
+    pub only: Vec<Phase>,
+
+    /// Skip these phases
+    #[clap(long, value_delimiter = ',')]
+    pub skip: Vec<Phase>,
+
+    /// Only sync files and tasks with this tag (repeatable)
+    #[clap(short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Stop at the first file which fails to render or link
+    #[clap(long)]
+    pub fail_fast: bool,
+
+    #[clap(flatten)]
+    pub answers: AnswerArgs,
+
+    #[clap(flatten)]
+    pub install: InstallArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Phase {
+    Ask,
+    Link,
+    Render,
+    Install,
+}
+
+#[derive(Clone, Debug, Default, clap::Parser)]
+pub struct InstallArgs {
+    /// Number of tasks to run in parallel
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// Stream task output instead of collapsing it into the progress line
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Number of output lines to show when a task fails
+    #[arg(long)]
+    pub tail: Option<usize>,
+}
+
+#[derive(Clone, Debug, clap::Parser)]
+pub struct WatchArgs {
+    /// Milliseconds to wait for further changes before re-rendering
+    #[arg(long)]
+    pub debounce: Option<u64>,
 }
 
 #[derive(Clone, Debug, clap::Parser)]
 pub struct CloneArgs {
-    // Git repository URL
+    /// Git repository URL, or `user/repo` for a GitHub repository
     pub url: String,
 
-    // destination directory
+    /// Destination directory
     #[clap(short, long, default_value = "~/.config")]
     pub dest: PathBuf,
+
+    /// Branch to check out instead of the default branch of the repository
+    #[arg(short, long)]
+    pub branch: Option<String>,
+
+    /// Only fetch the given number of most recent commits
+    #[arg(long)]
+    pub depth: Option<u32>,
+
+    /// Clone into the destination even if it is not empty, as long as none of its files
+    /// would be overwritten by the repository
+    #[arg(long)]
+    pub force: bool,
+
+    /// Only clone the repository without syncing it
+    #[arg(long)]
+    pub no_sync: bool,
+
+    #[clap(flatten)]
+    pub answers: AnswerArgs,
+
+    #[clap(flatten)]
+    pub install: InstallArgs,
 }
 
 #[derive(Clone, Debug, clap::Parser)]
@@ -107,6 +214,115 @@ pub struct AskArgs {
     /// Overwrite existing answers file
     #[arg(long)]
     pub force: bool,
+
+    #[clap(flatten)]
+    pub answers: AnswerArgs,
+}
+
+#[derive(Clone, Debug, Default, clap::Parser)]
+pub struct AnswerArgs {
+    /// Answer a parameter without prompting (repeatable)
+    #[arg(long = "answer", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub answers: Vec<(String, String)>,
+
+    /// TOML file with answers to use instead of prompting
+    #[arg(long)]
+    pub answers_file: Option<PathBuf>,
+
+    /// Never prompt; use defaults and fail on missing answers
+    #[arg(long)]
+    pub non_interactive: bool,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .ok_or_else(|| format!("invalid answer '{s}', expected KEY=VALUE"))
+}
+
+#[derive(Clone, Debug, clap::Parser)]
+pub struct AnswersArgs {
+    #[command(subcommand)]
+    pub command: AnswersCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum AnswersCommand {
+    /// List all parameters and their answers
+    List,
+
+    /// Print the answer of a single parameter
+    Get {
+        /// Name of the parameter
+        name: String,
+    },
+
+    /// Set the answer of a parameter
+    Set {
+        /// Name of the parameter
+        name: String,
+
+        /// New value
+        value: String,
+
+        /// Re-render templates afterwards
+        #[arg(long)]
+        render: bool,
+    },
+
+    /// Remove the answer of a parameter
+    Unset {
+        /// Name of the parameter
+        name: String,
+
+        /// Re-render templates afterwards
+        #[arg(long)]
+        render: bool,
+    },
+}
+
+#[derive(Clone, Debug, clap::Parser)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print every setting with its effective value. Repo settings come from the repo
+    /// config, personal settings from the user config at `$XDG_CONFIG_HOME/sams/config.toml`,
+    /// `SAMS_CFG_*` environment variables and flags.
+    Show {
+        /// Print where each value comes from
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+#[derive(Clone, Debug, clap::Parser)]
+pub struct MigrateConfigArgs {
+    /// Print the migrated config instead of writing it
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug, Default, clap::Parser)]
+pub struct PullArgs {
+    /// Answers for parameters added by the incoming commits
+    #[clap(flatten)]
+    pub answers: AnswerArgs,
+}
+
+#[derive(Clone, Debug, clap::Parser)]
+pub struct CommitArgs {
+    /// Commit message, by default a message listing the changed dotfiles is proposed
+    #[arg(short, long)]
+    pub message: Option<String>,
+
+    /// Use the proposed commit message without prompting
+    #[arg(long, conflicts_with = "message")]
+    pub no_edit: bool,
 }
 
 #[derive(Clone, Debug, clap::Parser)]
@@ -0,0 +1,118 @@
+use std::fs::{self, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{self as unix_fs, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tempfile::Builder;
+
+/// Write a file through a temporary file in the same directory which is renamed over it,
+/// so it is never left half written. The permissions of an existing file are kept.
+pub fn write(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    // Like `fs::write`, write to the target of a symlink instead of replacing the symlink
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_symlink() => fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve symlink: {}", path.display()))?,
+        _ => path.to_path_buf(),
+    };
+    let permissions = fs::metadata(&path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+
+    let mut file = Builder::new()
+        .prefix(&temp_prefix(&path))
+        .permissions(Permissions::from_mode(0o666))
+        .tempfile_in(parent(&path))
+        .with_context(|| format!("Failed to create temporary file for: {}", path.display()))?;
+    file.write_all(content.as_ref())
+        .and_then(|_| file.as_file().sync_all())
+        .with_context(|| format!("Failed to write temporary file for: {}", path.display()))?;
+    if let Some(permissions) = permissions {
+        fs::set_permissions(file.path(), permissions)
+            .with_context(|| format!("Failed to set permissions for: {}", path.display()))?;
+    }
+
+    file.persist(&path)
+        .with_context(|| format!("Failed to replace file: {}", path.display()))?;
+    Ok(())
+}
+
+/// Create a symlink at `path` pointing to `target` by renaming a temporary symlink over
+/// it, so there is no moment without a file at `path`.
+pub fn symlink(target: &Path, path: &Path) -> Result<()> {
+    let link = Builder::new()
+        .prefix(&temp_prefix(path))
+        .make_in(parent(path), |temp| unix_fs::symlink(target, temp))
+        .with_context(|| format!("Failed to create temporary symlink for: {}", path.display()))?;
+
+    link.into_temp_path()
+        .persist(path)
+        .with_context(|| format!("Failed to replace file: {}", path.display()))?;
+    Ok(())
+}
+
+fn parent(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Hidden name of the temporary file, so editors and the walker ignore it
+fn temp_prefix(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    format!(".{}.", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_write_keeps_permissions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+
+        write(&path, "first").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o750)).unwrap();
+        write(&path, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_through_symlink() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("target");
+        let link = dir.path().join("link");
+        fs::write(&target, "old").unwrap();
+        unix_fs::symlink(&target, &link).unwrap();
+
+        write(&link, "new").unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_symlink_replaces_existing_file() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("target");
+        let path = dir.path().join("path");
+        fs::write(&path, "existing").unwrap();
+
+        symlink(&target, &path).unwrap();
+        assert_eq!(fs::read_link(&path).unwrap(), target);
+
+        // Replacing a dangling symlink works as well
+        let other = dir.path().join("other");
+        symlink(&other, &path).unwrap();
+        assert_eq!(fs::read_link(&path).unwrap(), other);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use toml::Table;
+use toml_edit::{DocumentMut, Item};
+
+use crate::args::{AnswersArgs, AnswersCommand};
+use crate::atomic;
+use crate::commands::ask::parse_answer;
+use crate::commands::render::render;
+use crate::config::{Parameter, Value};
+use crate::context::read_answers;
+use crate::filter::Filter;
+use crate::format::Format;
+use crate::project::Project;
+
+pub fn answers(args: AnswersArgs, project: &Project) -> Result<()> {
+    let cfg = &project.config;
+    let answer_file = project.answer_file();
+
+    match args.command {
+        AnswersCommand::List => {
+            let doc = read_document(&answer_file)?;
+            list(&cfg.parameters, &doc);
+        },
+        AnswersCommand::Get { name } => {
+            let doc = read_document(&answer_file)?;
+            let value = doc
+                .get(&name)
+                .and_then(item_value)
+                .with_context(|| format!("No answer set for parameter '{name}'"))?;
+            println!("{}", display_value(&value));
+        },
+        AnswersCommand::Set {
+            name,
+            value,
+            render: rerender,
+        } => {
+            let param = find_parameter(&cfg.parameters, &name)?;
+            let value = parse_answer(param, &value)?;
+
+            let mut doc = read_document_or_default(&answer_file)?;
+            set_answer(&mut doc, &name, &value)?;
+            write_document(&answer_file, &doc)?;
+
+            println!("{} {} = {}", "✓      Set".green().bold(), name, value);
+            if rerender {
+                render(project, &Filter::default(), false)?;
+            }
+        },
+        AnswersCommand::Unset {
+            name,
+            render: rerender,
+        } => {
+            let mut doc = read_document(&answer_file)?;
+            if doc.remove(&name).is_none() {
+                bail!("No answer set for parameter '{name}'");
+            }
+            write_document(&answer_file, &doc)?;
+
+            println!("{} {}", "✓    Unset".green().bold(), name);
+            if rerender {
+                render(project, &Filter::default(), false)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn list(params: &[Parameter], doc: &DocumentMut) {
+    for param in params {
+        match doc.get(param.name()).and_then(item_value) {
+            Some(value) => println!("{} = {}", param.name().bold(), display_value(&value)),
+            None => println!("{} = {}", param.name().bold(), "<unset>".dimmed()),
+        }
+    }
+
+    // Answers without a matching parameter are kept but flagged.
+    for (key, item) in doc.iter() {
+        if params.iter().any(|p| p.name() == key) {
+            continue;
+        }
+        if let Some(value) = item_value(item) {
+            println!(
+                "{} = {} {}",
+                key.bold(),
+                display_value(&value),
+                "(unknown parameter)".yellow()
+            );
+        }
+    }
+}
+
+fn find_parameter<'a>(params: &'a [Parameter], name: &str) -> Result<&'a Parameter> {
+    params
+        .iter()
+        .find(|p| p.name() == name)
+        .with_context(|| format!("Unknown parameter '{name}'"))
+}
+
+/// Nested answers may be stored as `[table]` or `[[array]]` sections.
+fn item_value(item: &Item) -> Option<toml_edit::Value> {
+    item.clone().into_value().ok()
+}
+
+fn display_value(value: &toml_edit::Value) -> String {
+    match value.as_str() {
+        Some(s) => s.to_string(),
+        None => value.to_string().trim().to_string(),
+    }
+}
+
+/// Set an answer while keeping the comments and whitespace around it intact.
+pub(crate) fn set_answer(doc: &mut DocumentMut, name: &str, value: &Value) -> Result<()> {
+    let mut new = match value {
+        Value::Integer(i) => toml_edit::Value::from(*i),
+        Value::Float(f) => toml_edit::Value::from(*f),
+        Value::String(s) => toml_edit::Value::from(s.as_str()),
+        Value::Array(_) | Value::Table(_) => value
+            .to_string()
+            .parse()
+            .context("Failed to convert answer to TOML")?,
+    };
+
+    match doc.get_mut(name).and_then(Item::as_value_mut) {
+        Some(old) => {
+            *new.decor_mut() = old.decor().clone();
+            *old = new;
+        },
+        None => {
+            doc.insert(name, Item::Value(new));
+        },
+    }
+    Ok(())
+}
+
+/// Read the answer file as a TOML document, converting YAML and JSON answer files to TOML
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    let content = match Format::from_path(path) {
+        Format::Toml => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read answer file: {}", path.display()))?,
+        _ => toml::to_string(&read_answers(path)?).context("Failed to convert answers to TOML")?,
+    };
+    content
+        .parse()
+        .with_context(|| format!("Failed to parse answer file: {}", path.display()))
+}
+
+pub(crate) fn read_document_or_default(path: &Path) -> Result<DocumentMut> {
+    if path.exists() {
+        read_document(path)
+    } else {
+        Ok(DocumentMut::new())
+    }
+}
+
+pub(crate) fn write_document(path: &Path, doc: &DocumentMut) -> Result<()> {
+    let content = match Format::from_path(path) {
+        Format::Toml => doc.to_string(),
+        format => format.serialize(&toml::from_str::<Table>(&doc.to_string())?)?,
+    };
+    atomic::write(path, content)
+        .with_context(|| format!("Failed to write answers to file: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_answer_preserves_formatting() {
+        let mut doc: DocumentMut = "# my answers\nname = \"old\" # trailing\nport = 22\n"
+            .parse()
+            .unwrap();
+
+        set_answer(&mut doc, "name", &Value::String("new".into())).unwrap();
+        set_answer(&mut doc, "editor", &Value::String("nvim".into())).unwrap();
+
+        assert_eq!(
+            doc.to_string(),
+            "# my answers\nname = \"new\" # trailing\nport = 22\neditor = \"nvim\"\n"
+        );
+    }
+
+    #[test]
+    fn test_json_answer_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".sams-answers.json");
+        fs::write(&path, "{\"name\": \"me\"}").unwrap();
+
+        let mut doc = read_document(&path).unwrap();
+        set_answer(&mut doc, "port", &Value::Integer(22)).unwrap();
+        write_document(&path, &doc).unwrap();
+
+        let answers: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(answers, serde_json::json!({"name": "me", "port": 22}));
+    }
+
+    #[test]
+    fn test_display_value() {
+        let doc: DocumentMut = "name = \"me\"\nport = 22\n[[ids]]\nname = \"work\"\n"
+            .parse()
+            .unwrap();
+        let display = |key| display_value(&item_value(&doc[key]).unwrap());
+        assert_eq!(display("name"), "me");
+        assert_eq!(display("port"), "22");
+        assert_eq!(display("ids"), "[{ name = \"work\" }]");
+    }
+}
@@ -1,70 +1,353 @@
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 
-use anyhow::{Context, Result};
-use inquire::{Select, Text};
+use anyhow::{bail, Context, Result};
+use inquire::{Confirm, Select, Text};
 use toml;
 
-use crate::args::{AskArgs, GlobalArgs};
-use crate::config::{Config, DataType, Parameter, Value};
+use crate::args::{AnswerArgs, AskArgs};
+use crate::atomic;
+use crate::commands::answers::{read_document_or_default, set_answer, write_document};
+use crate::config::{DataType, Parameter, Value};
+use crate::context::read_answers;
+use crate::format::Format;
+use crate::project::Project;
 
-pub fn ask(args: AskArgs, global: &GlobalArgs) -> Result<()> {
-    let cfg = Config::from_file(&global.config_path)?;
-
-    let answer_file = global.root.join(&cfg.answer_file);
+pub fn ask(args: AskArgs, project: &Project) -> Result<()> {
+    let cfg = &project.config;
+    let answer_file = project.answer_file();
 
     if !args.force && answer_file.exists() {
         return Ok(());
     }
 
+    let sources = AnswerSources::from_args(&args.answers)?;
+    sources.check_known(&cfg.parameters)?;
+
     // Ask the user for each parameter and store the answers.
+    let answers = collect_answers(&cfg.parameters, &sources, args.answers.non_interactive)?;
+
+    // Serialize answers in the format of the answer file.
+    let content = Format::from_path(&answer_file).serialize(&answers)?;
+
+    atomic::write(&answer_file, content.as_bytes())
+        .with_context(|| format!("Failed to write answers to file: {:?}", answer_file))?;
+
+    Ok(())
+}
+
+/// Ask only for the parameters without an answer, keeping the existing answers and the
+/// layout of the answer file. Returns the names of the newly answered parameters.
+pub fn ask_missing(args: &AnswerArgs, project: &Project) -> Result<Vec<String>> {
+    let answer_file = project.answer_file();
+    let sources = AnswerSources::from_args(args)?;
+    sources.check_known(&project.config.parameters)?;
+
+    let mut doc = read_document_or_default(&answer_file)?;
+    let missing: Vec<Parameter> = project
+        .config
+        .parameters
+        .iter()
+        .filter(|param| !doc.contains_key(param.name()))
+        .cloned()
+        .collect();
+    if missing.is_empty() && answer_file.exists() {
+        return Ok(Vec::new());
+    }
+
+    let answers = collect_answers(&missing, &sources, args.non_interactive)?;
+    for param in &missing {
+        set_answer(&mut doc, param.name(), &answers[param.name()])?;
+    }
+    write_document(&answer_file, &doc)?;
+
+    Ok(missing
+        .iter()
+        .map(|param| param.name().to_string())
+        .collect())
+}
+
+/// Answers provided up front, looked up before falling back to a prompt.
+///
+/// Precedence is `--answer` flags, then `SAMS_ANSWER_<NAME>` environment
+/// variables, then the `--answers-file`.
+#[derive(Debug, Default)]
+struct AnswerSources {
+    cli: HashMap<String, String>,
+    file: HashMap<String, Value>,
+}
+
+impl AnswerSources {
+    fn from_args(args: &AnswerArgs) -> Result<Self> {
+        let file = match &args.answers_file {
+            Some(path) => read_answers(path)?
+                .into_iter()
+                .map(|(key, value)| (key, Value::from(value)))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            cli: args.answers.iter().cloned().collect(),
+            file,
+        })
+    }
+
+    /// The answer given up front for the parameter, if any
+    fn answer(&self, param: &Parameter) -> Result<Option<Value>> {
+        let name = param.name();
+        let input = self
+            .cli
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(env_var_name(name)).ok());
+        if let Some(input) = input {
+            return parse_answer(param, &input).map(Some);
+        }
+
+        // Values of the answers file are typed already, so they are not parsed again
+        match self.file.get(name) {
+            Some(value) => coerce_answer(param, value.clone())
+                .with_context(|| format!("Invalid answer for parameter '{name}' in answers file"))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn check_known(&self, params: &[Parameter]) -> Result<()> {
+        let unknown: Vec<&str> = self
+            .cli
+            .keys()
+            .filter(|key| !params.iter().any(|p| p.name() == key.as_str()))
+            .map(String::as_str)
+            .collect();
+
+        if !unknown.is_empty() {
+            bail!("Unknown parameters in --answer: {}", unknown.join(", "));
+        }
+        Ok(())
+    }
+}
+
+fn env_var_name(name: &str) -> String {
+    format!("SAMS_ANSWER_{}", name.to_uppercase())
+}
+
+fn collect_answers(
+    params: &[Parameter],
+    sources: &AnswerSources,
+    non_interactive: bool,
+) -> Result<HashMap<String, Value>> {
     let mut answers: HashMap<String, Value> = HashMap::new();
+    let mut missing: Vec<String> = Vec::new();
 
-    for param in cfg.parameters {
-        match param {
-            Parameter::Select {
-                name,
-                description,
-                options,
-            } => {
-                let msg = description.as_deref().unwrap_or(&name);
-                let ans = Select::new(msg, options)
-                    .with_formatter(&|opt| opt.to_string())
-                    .prompt()?;
-                answers.insert(name, ans);
-            },
-            Parameter::Text {
-                name,
-                description,
-                default,
-                placeholder,
-                data_type,
-            } => {
-                let msg = description.as_deref().unwrap_or(&name);
-                let mut prompt = Text::new(msg);
-
-                if let Some(default) = default.as_deref() {
-                    prompt = prompt.with_default(default);
-                }
+    for param in params {
+        if let Some(ans) = sources.answer(param)? {
+            answers.insert(param.name().to_string(), ans);
+            continue;
+        }
+
+        if non_interactive {
+            match default_answer(param)? {
+                Some(ans) => answers.insert(param.name().to_string(), ans),
+                None => {
+                    missing.push(param.name().to_string());
+                    continue;
+                },
+            };
+            continue;
+        }
+
+        let ans = prompt(param)?;
+        answers.insert(param.name().to_string(), ans);
+    }
+
+    if !missing.is_empty() {
+        bail!(
+            "Missing answers for parameters: {} (provide them with --answer KEY=VALUE, \
+             SAMS_ANSWER_<NAME> or --answers-file)",
+            missing.join(", ")
+        );
+    }
+
+    Ok(answers)
+}
+
+fn prompt(param: &Parameter) -> Result<Value> {
+    match param {
+        Parameter::Select {
+            name,
+            description,
+            options,
+        } => {
+            let msg = description.as_deref().unwrap_or(name);
+            let ans = Select::new(msg, options.clone())
+                .with_formatter(&|opt| opt.to_string())
+                .prompt()?;
+            Ok(ans)
+        },
+        Parameter::Text {
+            name,
+            description,
+            default,
+            placeholder,
+            data_type,
+        } => {
+            let msg = description.as_deref().unwrap_or(name);
+            let mut prompt = Text::new(msg);
+
+            if let Some(default) = default.as_deref() {
+                prompt = prompt.with_default(default);
+            }
+
+            if let Some(placeholder) = placeholder.as_deref() {
+                prompt = prompt.with_placeholder(placeholder);
+            }
 
-                if let Some(placeholder) = placeholder.as_deref() {
-                    prompt = prompt.with_placeholder(placeholder);
+            let ans = prompt.prompt()?;
+            parse_input(&ans, *data_type)
+        },
+        Parameter::List {
+            name,
+            description,
+            fields,
+        } => {
+            let msg = description.as_deref().unwrap_or(name);
+            let mut items = Vec::new();
+
+            loop {
+                let question = match items.len() {
+                    0 => format!("{msg}: add an item?"),
+                    n => format!("{msg}: add another item? ({n} so far)"),
+                };
+                if !Confirm::new(&question).with_default(false).prompt()? {
+                    break;
                 }
+                items.push(prompt_fields(fields)?);
+            }
 
-                let ans = prompt.prompt()?;
-                answers.insert(name, parse_input(&ans, data_type)?);
-            },
-        };
+            Ok(Value::Array(items))
+        },
+        Parameter::Table { fields, .. } => prompt_fields(fields),
     }
+}
 
-    // Serialize answers to TOML.
-    let toml_string = toml::to_string(&answers)?;
+fn prompt_fields(fields: &[Parameter]) -> Result<Value> {
+    fields
+        .iter()
+        .map(|field| Ok((field.name().to_string(), prompt(field)?)))
+        .collect::<Result<_>>()
+        .map(Value::Table)
+}
 
-    // Write the answers using std::fs::write (simpler than OpenOptions)
-    fs::write(&answer_file, toml_string.as_bytes())
-        .with_context(|| format!("Failed to write answers to file: {:?}", answer_file))?;
+/// The answer used for a parameter when prompting is not possible.
+///
+/// Lists default to being empty, tables default to the defaults of their fields.
+fn default_answer(param: &Parameter) -> Result<Option<Value>> {
+    match param {
+        Parameter::Select { .. } => Ok(None),
+        Parameter::Text {
+            name,
+            default,
+            data_type,
+            ..
+        } => default
+            .as_deref()
+            .map(|default| {
+                parse_input(default, *data_type)
+                    .with_context(|| format!("Invalid default for parameter '{name}'"))
+            })
+            .transpose(),
+        Parameter::List { .. } => Ok(Some(Value::Array(Vec::new()))),
+        Parameter::Table { fields, .. } => {
+            let mut table = BTreeMap::new();
+            for field in fields {
+                match default_answer(field)? {
+                    Some(value) => table.insert(field.name().to_string(), value),
+                    None => return Ok(None),
+                };
+            }
+            Ok(Some(Value::Table(table)))
+        },
+    }
+}
 
-    Ok(())
+pub(crate) fn parse_answer(param: &Parameter, input: &str) -> Result<Value> {
+    match param {
+        Parameter::Select { name, options, .. } => options
+            .iter()
+            .find(|opt| opt.to_string() == input)
+            .cloned()
+            .with_context(|| {
+                let options: Vec<String> = options.iter().map(Value::to_string).collect();
+                format!(
+                    "Invalid answer '{input}' for parameter '{name}', expected one of: {}",
+                    options.join(", ")
+                )
+            }),
+        Parameter::Text {
+            name, data_type, ..
+        } => parse_input(input, *data_type)
+            .with_context(|| format!("Invalid answer for parameter '{name}'")),
+        Parameter::List { name, .. } | Parameter::Table { name, .. } => {
+            let value = parse_inline_toml(input)
+                .with_context(|| format!("Invalid answer for parameter '{name}'"))?;
+            coerce_answer(param, value)
+        },
+    }
+}
+
+/// Parse a nested answer given as an inline TOML value, e.g. `[{ name = "me" }]`.
+fn parse_inline_toml(input: &str) -> Result<Value> {
+    let mut table: toml::Table = toml::from_str(&format!("value = {input}"))
+        .with_context(|| format!("Failed to parse '{input}' as inline TOML"))?;
+    let value = table.remove("value").context("Missing value")?;
+    value.try_into().context("Unsupported TOML value")
+}
+
+/// Check a value against the shape of a parameter, converting scalars as needed.
+fn coerce_answer(param: &Parameter, value: Value) -> Result<Value> {
+    match (param, value) {
+        (Parameter::List { fields, name, .. }, Value::Array(items)) => items
+            .into_iter()
+            .map(|item| coerce_fields(fields, item))
+            .collect::<Result<_>>()
+            .map(Value::Array)
+            .with_context(|| format!("Invalid item in list '{name}'")),
+        (Parameter::List { name, .. }, _) => bail!("Expected a list for parameter '{name}'"),
+        (Parameter::Table { fields, name, .. }, value) => {
+            coerce_fields(fields, value).with_context(|| format!("Invalid table '{name}'"))
+        },
+        (param, Value::Array(_) | Value::Table(_)) => {
+            bail!("Expected a single value for parameter '{}'", param.name())
+        },
+        (Parameter::Select { options, .. }, value) if options.contains(&value) => Ok(value),
+        (param, value) => parse_answer(param, &value.to_string()),
+    }
+}
+
+fn coerce_fields(fields: &[Parameter], value: Value) -> Result<Value> {
+    let Value::Table(mut table) = value else {
+        bail!("Expected a table, got '{value}'");
+    };
+
+    if let Some(unknown) = table
+        .keys()
+        .find(|k| !fields.iter().any(|f| f.name() == *k))
+    {
+        bail!("Unknown field '{unknown}'");
+    }
+
+    let mut coerced = BTreeMap::new();
+    for field in fields {
+        let value = match table.remove(field.name()) {
+            Some(value) => coerce_answer(field, value)?,
+            None => default_answer(field)?
+                .with_context(|| format!("Missing field '{}'", field.name()))?,
+        };
+        coerced.insert(field.name().to_string(), value);
+    }
+    Ok(Value::Table(coerced))
 }
 
 fn parse_input(input: &str, data_type: DataType) -> Result<Value> {
@@ -100,4 +383,142 @@ mod tests {
             Value::String("hello".into())
         );
     }
+
+    fn params() -> Vec<Parameter> {
+        vec![
+            Parameter::Select {
+                name: "shell".into(),
+                description: None,
+                options: vec![Value::String("bash".into()), Value::String("zsh".into())],
+            },
+            Parameter::Text {
+                name: "port".into(),
+                description: None,
+                default: Some("22".into()),
+                placeholder: None,
+                data_type: DataType::Int,
+            },
+            Parameter::Text {
+                name: "email".into(),
+                description: None,
+                default: None,
+                placeholder: None,
+                data_type: DataType::Str,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_collect_answers_non_interactive() {
+        let sources = AnswerSources {
+            cli: HashMap::from([("shell".into(), "zsh".into())]),
+            file: HashMap::from([("email".into(), Value::String("me@example.com".into()))]),
+        };
+
+        let answers = collect_answers(&params(), &sources, true).unwrap();
+        assert_eq!(answers["shell"], Value::String("zsh".into()));
+        assert_eq!(answers["port"], Value::Integer(22));
+        assert_eq!(answers["email"], Value::String("me@example.com".into()));
+    }
+
+    #[test]
+    fn test_collect_answers_reports_all_missing() {
+        let err = collect_answers(&params(), &AnswerSources::default(), true).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("shell, email"), "unexpected error: {msg}");
+    }
+
+    #[test]
+    fn test_parse_answer_rejects_unknown_option() {
+        let params = params();
+        assert!(parse_answer(&params[0], "fish").is_err());
+        assert!(parse_answer(&params[1], "not a number").is_err());
+    }
+
+    #[test]
+    fn test_parse_answer_nested_list() {
+        let param = Parameter::List {
+            name: "identities".into(),
+            description: None,
+            fields: vec![
+                Parameter::Text {
+                    name: "name".into(),
+                    description: None,
+                    default: None,
+                    placeholder: None,
+                    data_type: DataType::Str,
+                },
+                Parameter::Text {
+                    name: "signing_key".into(),
+                    description: None,
+                    default: Some("".into()),
+                    placeholder: None,
+                    data_type: DataType::Str,
+                },
+            ],
+        };
+
+        let value = parse_answer(&param, r#"[{ name = "work" }, { name = "home" }]"#).unwrap();
+        let expected = Value::Array(vec![
+            Value::Table(BTreeMap::from([
+                ("name".into(), Value::String("work".into())),
+                ("signing_key".into(), Value::String("".into())),
+            ])),
+            Value::Table(BTreeMap::from([
+                ("name".into(), Value::String("home".into())),
+                ("signing_key".into(), Value::String("".into())),
+            ])),
+        ]);
+        assert_eq!(value, expected);
+
+        assert!(parse_answer(&param, r#"[{ email = "me" }]"#).is_err());
+        assert!(parse_answer(&param, r#""not a list""#).is_err());
+
+        let toml_string = toml::to_string(&HashMap::from([("identities", value)])).unwrap();
+        assert!(toml_string.contains("[[identities]]"));
+    }
+
+    #[test]
+    fn test_answers_file_values_keep_their_type() {
+        let answers: toml::Table = toml::from_str(
+            "scale = 1.0
+identities = [{ name = \"work\" }]",
+        )
+        .unwrap();
+        let sources = AnswerSources {
+            file: answers
+                .into_iter()
+                .map(|(key, value)| (key, Value::from(value)))
+                .collect(),
+            ..AnswerSources::default()
+        };
+        let params = vec![
+            Parameter::Select {
+                name: "scale".into(),
+                description: None,
+                options: vec![Value::Float(1.0), Value::Float(1.5)],
+            },
+            Parameter::List {
+                name: "identities".into(),
+                description: None,
+                fields: vec![Parameter::Text {
+                    name: "name".into(),
+                    description: None,
+                    default: None,
+                    placeholder: None,
+                    data_type: DataType::Str,
+                }],
+            },
+        ];
+
+        let answers = collect_answers(&params, &sources, true).unwrap();
+        assert_eq!(answers["scale"], Value::Float(1.0));
+        assert_eq!(
+            answers["identities"],
+            Value::Array(vec![Value::Table(BTreeMap::from([(
+                "name".into(),
+                Value::String("work".into())
+            )]))])
+        );
+    }
 }
@@ -0,0 +1,373 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use colored::Colorize;
+use jsonschema::JSONSchema;
+use schemars::schema_for;
+
+use crate::args::GlobalArgs;
+use crate::config::{Config, Parameter, Task};
+use crate::context::builtin_context;
+use crate::format::Format;
+use crate::generated::{in_git_repo, Generated};
+use crate::project::Project;
+use crate::template::template_variables;
+use crate::walk::WalkOptions;
+
+/// Words Tera reserves, which can not be used as variable names
+const TERA_KEYWORDS: [&str; 9] = [
+    "and", "or", "not", "in", "is", "true", "false", "True", "False",
+];
+
+/// Validate the config and the templates, printing every problem found
+pub fn check(global: &GlobalArgs) -> Result<()> {
+    let (_, config_path) = Project::locate(global)?;
+    let problems = check_schema(&config_path)?;
+    if !problems.is_empty() {
+        return report(&config_path, problems);
+    }
+
+    let project = Project::load(global)?;
+    let cfg = &project.config;
+    let mut problems = Vec::new();
+    check_parameters(&cfg.parameters, "", &mut problems);
+    for task in all_tasks(cfg) {
+        check_task(task, &project, &mut problems);
+    }
+    check_templates(&project, &mut problems)?;
+    for warning in tracked_warnings(&project)? {
+        println!("{} {}", "!   Warning".bold().yellow(), warning);
+    }
+    report(&config_path, problems)
+}
+
+fn report(config_path: &Path, problems: Vec<String>) -> Result<()> {
+    for problem in &problems {
+        println!("{} {}", "✗   Problem".bold().red(), problem);
+    }
+    if !problems.is_empty() {
+        bail!(
+            "Found {} problems with '{}'",
+            problems.len(),
+            config_path.display()
+        );
+    }
+    println!("{} {}", "✓   Checked".bold().green(), config_path.display());
+    Ok(())
+}
+
+/// Files generated by sams which are tracked by git, which does not make the config
+/// invalid but should be fixed before they are pushed
+fn tracked_warnings(project: &Project) -> Result<Vec<String>> {
+    if !in_git_repo(&project.root) {
+        return Ok(Vec::new());
+    }
+    let tracked = Generated::load(project)?.tracked(&project.root)?;
+    Ok(tracked
+        .iter()
+        .map(|path| {
+            format!(
+                "'{}' is generated by sams but tracked by git, remove it from the repository \
+                 with `git rm --cached`",
+                path.display()
+            )
+        })
+        .collect())
+}
+
+/// Validate the config file against the JSON schema of the config, so every wrong value
+/// is reported with its location instead of only the first one.
+fn check_schema(config_path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file at '{}'", config_path.display()))?;
+    let instance: serde_json::Value = Format::from_path(config_path)
+        .parse(&content)
+        .with_context(|| format!("Failed to parse config file at '{}'", config_path.display()))?;
+
+    let schema =
+        serde_json::to_value(schema_for!(Config)).context("Failed to generate JSON schema")?;
+    let schema = JSONSchema::compile(&schema)
+        .map_err(|err| anyhow!("Failed to compile JSON schema: {}", err))?;
+
+    let problems = match schema.validate(&instance) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|err| {
+                let path = err.instance_path.to_string();
+                let path = if path.is_empty() { "/" } else { &path };
+                format!("{}: {}", path, err)
+            })
+            .collect(),
+    };
+    Ok(problems)
+}
+
+/// Check that parameter names are unique valid identifiers and selects have options.
+/// `prefix` is the path of the parameter containing `params` as fields.
+fn check_parameters(params: &[Parameter], prefix: &str, problems: &mut Vec<String>) {
+    let mut names = HashSet::new();
+    for param in params {
+        let name = format!("{}{}", prefix, param.name());
+        if !is_identifier(param.name()) {
+            problems.push(format!(
+                "Parameter '{}' is not a valid Tera identifier, use only letters, digits and \
+                 underscores and do not start with a digit",
+                name
+            ));
+        }
+        if !names.insert(param.name()) {
+            problems.push(format!("Parameter '{}' is defined more than once", name));
+        }
+
+        match param {
+            Parameter::Select { options, .. } if options.is_empty() => {
+                problems.push(format!("Select parameter '{}' has no options", name));
+            },
+            Parameter::List { fields, .. } | Parameter::Table { fields, .. } => {
+                check_parameters(fields, &format!("{}.", name), problems);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !TERA_KEYWORDS.contains(&name)
+}
+
+/// The tasks and the tasks of all hooks
+fn all_tasks(cfg: &Config) -> impl Iterator<Item = &Task> {
+    let hooks = &cfg.hooks;
+    cfg.tasks
+        .iter()
+        .chain(&hooks.pre_sync)
+        .chain(&hooks.post_link)
+        .chain(&hooks.post_render)
+        .chain(&hooks.post_sync)
+        .chain(hooks.on_change.iter().flat_map(|hook| &hook.tasks))
+}
+
+fn check_task(task: &Task, project: &Project, problems: &mut Vec<String>) {
+    match (&task.script, &task.run) {
+        (Some(script), None) => {
+            let path = project.resolve(&task.workdir).join(script);
+            if !path.is_file() {
+                problems.push(format!(
+                    "Script of task '{}' does not exist: {}",
+                    task.display_name(),
+                    path.display()
+                ));
+            }
+        },
+        (None, Some(_)) => {},
+        _ => problems.push(format!(
+            "Task '{}' must define either `script` or `run`",
+            task.display_name()
+        )),
+    }
+}
+
+/// Check that templates and templated link paths only use variables defined by a
+/// parameter or built into the context
+fn check_templates(project: &Project, problems: &mut Vec<String>) -> Result<()> {
+    let cfg = &project.config;
+    let mut defined: BTreeSet<String> = cfg
+        .parameters
+        .iter()
+        .map(|param| param.name().to_string())
+        .collect();
+    if let serde_json::Value::Object(builtins) = builtin_context().into_json() {
+        defined.extend(builtins.into_iter().map(|(name, _)| name));
+    }
+
+    let mut sources = Vec::new();
+    for suffix in [&cfg.template_suffix, &cfg.link_suffix] {
+        for entry in WalkOptions::from_config(cfg)
+            .with_extension(suffix.clone())
+            .walk(&project.root)
+            .context("Failed to walk directory")?
+        {
+            let path = entry.context("Failed to walk directory")?.into_path();
+            let name = path
+                .strip_prefix(&project.root)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            let is_template = path
+                .extension()
+                .is_some_and(|ext| ext == cfg.template_suffix.as_str());
+            sources.push((path, name, is_template));
+        }
+    }
+    sources.sort();
+
+    for (path, name, is_template) in sources {
+        let variables = if is_template {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read template: {}", path.display()))?;
+            template_variables(&name, &content)
+        } else {
+            // The destination of a link is its path without the suffix, rendered as a template
+            let destination = Path::new(&name).with_extension("");
+            template_variables(&name, &destination.display().to_string())
+        };
+
+        match variables {
+            Ok(variables) => {
+                for variable in variables.difference(&defined) {
+                    problems.push(format!(
+                        "{} uses '{}', which is neither a parameter nor a built-in variable",
+                        name, variable
+                    ));
+                }
+            },
+            Err(err) => problems.push(format!("{:#}", err)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::git;
+
+    #[test]
+    fn test_check_parameters() {
+        let config: Config = toml::from_str(
+            r#"
+[[parameters]]
+kind = "text"
+name = "user-name"
+
+[[parameters]]
+kind = "select"
+name = "theme"
+options = []
+
+[[parameters]]
+kind = "list"
+name = "hosts"
+fields = [
+  { kind = "text", name = "name" },
+  { kind = "text", name = "name" },
+]
+
+[[parameters]]
+kind = "text"
+name = "theme"
+"#,
+        )
+        .unwrap();
+
+        let mut problems = Vec::new();
+        check_parameters(&config.parameters, "", &mut problems);
+        assert_eq!(problems.len(), 4, "{:#?}", problems);
+        assert!(problems[0].starts_with("Parameter 'user-name' is not a valid Tera identifier"));
+        assert_eq!(problems[1], "Select parameter 'theme' has no options");
+        assert_eq!(
+            problems[2],
+            "Parameter 'hosts.name' is defined more than once"
+        );
+        assert_eq!(problems[3], "Parameter 'theme' is defined more than once");
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("sams.toml");
+        fs::write(
+            &config_path,
+            "respect_gitignore = \"yes\"\n[[parameters]]\nkind = \"number\"\nname = \"age\"",
+        )
+        .unwrap();
+
+        let problems = check_schema(&config_path).unwrap();
+        assert_eq!(problems.len(), 2, "{:#?}", problems);
+        assert!(
+            problems[0].starts_with("/parameters/0: "),
+            "{}",
+            problems[0]
+        );
+        assert!(
+            problems[1].starts_with("/respect_gitignore: "),
+            "{}",
+            problems[1]
+        );
+    }
+
+    #[test]
+    fn test_check_templates_and_tasks() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("sams.toml"),
+            r#"
+[[parameters]]
+kind = "text"
+name = "user"
+
+[[tasks]]
+script = "missing.sh"
+"#,
+        )
+        .unwrap();
+        fs::write(
+            root.join("gitconfig.tpl"),
+            "{{ user }} {{ email }} {{ os }}",
+        )
+        .unwrap();
+        fs::write(root.join("{{ home }}.ln"), "").unwrap();
+        fs::write(root.join("broken.tpl"), "{{ user").unwrap();
+
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(root.to_path_buf()),
+        })
+        .unwrap();
+        let mut problems = Vec::new();
+        check_task(&project.config.tasks[0], &project, &mut problems);
+        check_templates(&project, &mut problems).unwrap();
+
+        assert_eq!(problems.len(), 4, "{:#?}", problems);
+        assert!(problems[0].starts_with("Script of task 'missing.sh' does not exist"));
+        assert!(problems[1].starts_with("Failed to parse template: broken.tpl:1:"));
+        assert_eq!(
+            problems[2],
+            "gitconfig.tpl uses 'email', which is neither a parameter nor a built-in variable"
+        );
+        assert_eq!(
+            problems[3],
+            "{{ home }}.ln uses 'home', which is neither a parameter nor a built-in variable"
+        );
+    }
+
+    #[test]
+    fn test_tracked_warnings() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("sams.toml"), "").unwrap();
+        fs::write(root.join(".sams-answers.toml"), "").unwrap();
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(root.to_path_buf()),
+        })
+        .unwrap();
+        assert!(tracked_warnings(&project).unwrap().is_empty());
+
+        git::run(root, ["init", "--quiet"]).unwrap();
+        git::run(root, ["add", "sams.toml", ".sams-answers.toml"]).unwrap();
+        let warnings = tracked_warnings(&project).unwrap();
+        assert_eq!(warnings.len(), 1, "{:#?}", warnings);
+        assert!(warnings[0].starts_with("'.sams-answers.toml' is generated by sams but tracked"));
+    }
+}
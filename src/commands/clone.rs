@@ -1,27 +1,326 @@
-use std::process::Command;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use tempfile::Builder;
 
 use crate::args::{CloneArgs, GlobalArgs, SyncArgs};
+use crate::commands::commit::{display_paths, split};
 use crate::commands::sync::sync;
+use crate::git;
+use crate::project::Project;
+use crate::settings::Layer;
 
 pub fn clone(args: CloneArgs, global: GlobalArgs) -> Result<()> {
+    let url = resolve_url(&args.url);
+    let dest = expand_tilde(&args.dest);
+
     // Clone
-    Command::new("git")
-        .arg("clone")
-        .arg(&args.url)
-        .arg(&args.dest)
-        .spawn()?
-        .wait()?;
+    let non_empty = fs::read_dir(&dest).is_ok_and(|mut entries| entries.next().is_some());
+    if dest.exists() && !dest.is_dir() {
+        bail!("Destination '{}' is not a directory", dest.display());
+    }
+    if non_empty && !args.force {
+        bail!(
+            "Destination '{}' is not empty, use --force to clone into it anyway",
+            dest.display()
+        );
+    }
+    if non_empty {
+        clone_into_existing(&url, &dest, &args)?;
+    } else {
+        let mut clone = clone_args(&url, &args);
+        clone.push(dest.clone().into());
+        git::run(Path::new("."), clone)?;
+    }
+
+    if args.no_sync {
+        return Ok(());
+    }
 
     // Sync
-    sync(
-        SyncArgs { ask: true },
+    let project = Project::load_with(
         &GlobalArgs {
-            root: args.dest.clone(),
+            root: Some(dest),
             ..global
         },
+        Layer::from_install_args(&args.install),
+    )?;
+    sync(
+        SyncArgs {
+            ask: true,
+            paths: Vec::new(),
+            only: Vec::new(),
+            skip: Vec::new(),
+            tags: Vec::new(),
+            fail_fast: false,
+            answers: args.answers,
+            install: args.install,
+        },
+        &project,
     )?;
 
     Ok(())
 }
+
+/// Arguments of `git clone` with the branch and depth of the arguments, without the
+/// destination
+fn clone_args(url: &str, args: &CloneArgs) -> Vec<OsString> {
+    let mut clone = vec!["clone".into()];
+    if let Some(branch) = &args.branch {
+        clone.extend(["--branch".into(), branch.into()]);
+    }
+    if let Some(depth) = args.depth {
+        clone.extend(["--depth".into(), depth.to_string().into()]);
+    }
+    clone.push(url.into());
+    clone
+}
+
+/// Git refuses to clone into a non-empty directory, so the repository is cloned without a
+/// working tree next to the existing files, moved into place and then checked out.
+/// Existing files are left alone, the clone is refused if the repository contains any of
+/// them.
+fn clone_into_existing(url: &str, dest: &Path, args: &CloneArgs) -> Result<()> {
+    let git_dir = dest.join(".git");
+    if git_dir.exists() {
+        bail!(
+            "Destination '{}' already is a git repository",
+            dest.display()
+        );
+    }
+
+    let temp = Builder::new()
+        .prefix(".sams-clone.")
+        .tempdir_in(dest)
+        .with_context(|| {
+            format!(
+                "Failed to create temporary directory in: {}",
+                dest.display()
+            )
+        })?;
+    let mut clone = clone_args(url, args);
+    clone.extend(["--no-checkout".into(), temp.path().into()]);
+    // Relative URLs are relative to the current directory, like the destination
+    git::run(Path::new("."), clone)?;
+
+    let files = git::output(temp.path(), ["ls-tree", "-r", "-z", "--name-only", "HEAD"])?;
+    let conflicts = conflicts(dest, split(&files));
+    if !conflicts.is_empty() {
+        bail!(
+            "The repository would overwrite these files in '{}': {}\nhelp: move them out of \
+             the way and clone again",
+            dest.display(),
+            display_paths(&conflicts)
+        );
+    }
+
+    fs::rename(temp.path().join(".git"), &git_dir)
+        .with_context(|| format!("Failed to move repository to: {}", dest.display()))?;
+    if let Err(err) = git::run(dest, ["reset", "--hard", "--quiet"]) {
+        fs::remove_dir_all(&git_dir)
+            .with_context(|| format!("Failed to remove: {}", git_dir.display()))?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The existing paths in `dest` a checkout of the repository files would replace: the files
+/// themselves and anything but a directory where the repository has a directory
+fn conflicts(dest: &Path, files: impl Iterator<Item = PathBuf>) -> Vec<PathBuf> {
+    let mut conflicts = Vec::new();
+    for file in files {
+        let conflict = file.ancestors().find(|path| {
+            let Ok(metadata) = dest.join(path).symlink_metadata() else {
+                return false;
+            };
+            *path == file || !metadata.is_dir()
+        });
+        match conflict {
+            Some(path) if !path.as_os_str().is_empty() && !conflicts.iter().any(|c| c == path) => {
+                conflicts.push(path.to_path_buf())
+            },
+            _ => {},
+        }
+    }
+    conflicts
+}
+
+/// Expand GitHub shorthand like `user/repo` to a clone URL, other URLs and local paths are
+/// kept as they are
+fn resolve_url(url: &str) -> String {
+    let shorthand = match url.split_once('/') {
+        Some((user, repo)) => {
+            let valid = |part: &str| {
+                !part.is_empty()
+                    && !part.starts_with('.')
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            };
+            valid(user) && valid(repo) && !Path::new(url).exists()
+        },
+        None => false,
+    };
+    if shorthand {
+        let repo = url.strip_suffix(".git").unwrap_or(url);
+        format!("https://github.com/{}.git", repo)
+    } else {
+        url.to_string()
+    }
+}
+
+/// Replace a leading `~` with the home directory
+fn expand_tilde(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    match (components.next(), env::var_os("HOME")) {
+        (Some(first), Some(home)) if first.as_os_str() == OsStr::new("~") => {
+            PathBuf::from(home).join(components.as_path())
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::git::test_utils::{global, init};
+
+    #[test]
+    fn test_resolve_url() {
+        assert_eq!(
+            resolve_url("AnH0ang/dotfiles"),
+            "https://github.com/AnH0ang/dotfiles.git"
+        );
+        assert_eq!(
+            resolve_url("AnH0ang/dotfiles.git"),
+            "https://github.com/AnH0ang/dotfiles.git"
+        );
+        for url in [
+            "https://gitlab.com/me/dotfiles.git",
+            "git@github.com:me/dotfiles.git",
+            "./me/dotfiles",
+            "/srv/dotfiles",
+            "dotfiles",
+        ] {
+            assert_eq!(resolve_url(url), url);
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("nvim")).unwrap();
+        fs::write(dir.path().join("nvim/init.lua"), "").unwrap();
+        fs::write(dir.path().join("zsh"), "").unwrap();
+
+        let files = [
+            "nvim/init.lua",
+            "nvim/lua/plugins.lua",
+            "zsh/zshrc",
+            "zsh/env",
+            "gitconfig",
+        ];
+        assert_eq!(
+            conflicts(dir.path(), files.into_iter().map(PathBuf::from)),
+            [PathBuf::from("nvim/init.lua"), PathBuf::from("zsh")]
+        );
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        assert_eq!(expand_tilde(Path::new("~/.config")), home.join(".config"));
+        assert_eq!(expand_tilde(Path::new("~")), home);
+        assert_eq!(expand_tilde(Path::new("/tmp/~")), PathBuf::from("/tmp/~"));
+        assert_eq!(expand_tilde(Path::new("~user")), PathBuf::from("~user"));
+    }
+
+    fn parse_args(args: &[&str]) -> CloneArgs {
+        CloneArgs::parse_from(["clone"].iter().chain(args).chain(&["--no-sync"]))
+    }
+
+    /// A path to `path` relative to the current directory
+    fn relative(path: &Path) -> PathBuf {
+        let cwd = env::current_dir().unwrap();
+        let up = cwd.components().skip(1).map(|_| Path::new(".."));
+        up.collect::<PathBuf>()
+            .join(path.strip_prefix("/").unwrap())
+    }
+
+    #[test]
+    fn test_force_clone_with_relative_paths() {
+        let dir = tempdir().unwrap();
+        let origin = init(&dir.path().join("origin"));
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("existing"), "kept").unwrap();
+        let (url, dest_arg) = (relative(&origin), relative(&dest));
+        let args = [url.to_str().unwrap(), "--dest", dest_arg.to_str().unwrap()];
+        clone(
+            parse_args(&[&args[..], &["--force"]].concat()),
+            global(&dest),
+        )
+        .unwrap();
+        assert!(dest.join("sams.toml").is_file());
+    }
+
+    #[test]
+    fn test_clone_into_existing_directory() {
+        let dir = tempdir().unwrap();
+        let origin = init(&dir.path().join("origin"));
+        let url = origin.to_str().unwrap();
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("existing"), "kept").unwrap();
+        let dest_arg = dest.to_str().unwrap();
+
+        let err = clone(parse_args(&[url, "--dest", dest_arg]), global(&dest)).unwrap_err();
+        assert!(err.to_string().contains("use --force"), "{}", err);
+
+        fs::write(dest.join("sams.toml"), "mine").unwrap();
+        let err = clone(
+            parse_args(&[url, "--dest", dest_arg, "--force"]),
+            global(&dest),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The repository would overwrite these files in"),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains(": sams.toml\n"), "{}", err);
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 2);
+        fs::remove_file(dest.join("sams.toml")).unwrap();
+
+        clone(
+            parse_args(&[url, "--dest", dest_arg, "--force", "--branch", "main"]),
+            global(&dest),
+        )
+        .unwrap();
+        assert!(dest.join(".git").is_dir());
+        assert!(dest.join("sams.toml").is_file());
+        assert_eq!(fs::read_to_string(dest.join("existing")).unwrap(), "kept");
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 3);
+
+        let err = clone(
+            parse_args(&[
+                url,
+                "--dest",
+                dir.path().join("new").to_str().unwrap(),
+                "-b",
+                "nope",
+            ]),
+            global(&dest),
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("`git clone` failed"), "{}", err);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use colored::Colorize;
+use inquire::{Confirm, Text};
+
+use crate::args::CommitArgs;
+use crate::config::Config;
+use crate::generated::Generated;
+use crate::git;
+use crate::project::Project;
+
+/// Commit all changes below the root, except the files generated by sams. The files to
+/// stage are listed and confirmed first, unless the message is given or not edited.
+pub fn commit(args: CommitArgs, project: &Project) -> Result<()> {
+    let root = &project.root;
+    let generated = Generated::load(project)?;
+
+    let staged = git::output(
+        root,
+        ["diff", "--cached", "--name-only", "--relative", "-z"],
+    )?;
+    let staged = generated_files(&staged, &generated);
+    if !staged.is_empty() {
+        bail!(
+            "Refusing to commit files generated by sams: {}\nhelp: unstage them with `git \
+             restore --staged`",
+            display_paths(&staged)
+        );
+    }
+
+    // Stage everything else
+    let changes = git::output(
+        root,
+        [
+            "ls-files",
+            "--modified",
+            "--deleted",
+            "--others",
+            "--exclude-standard",
+            "-z",
+        ],
+    )?;
+    let mut paths = Vec::new();
+    for path in split(&changes) {
+        if generated.contains(&path) {
+            println!("{} {} (generated)", "    Skipping".dimmed(), path.display());
+        } else {
+            paths.push(path);
+        }
+    }
+    if !paths.is_empty() {
+        for path in &paths {
+            println!("{} {}", "      Adding".bold(), path.display());
+        }
+        let confirmed = args.message.is_some()
+            || args.no_edit
+            || Confirm::new("Commit these files?")
+                .with_default(true)
+                .prompt()?;
+        if !confirmed {
+            println!("{} nothing was staged", "     Aborted".bold());
+            return Ok(());
+        }
+        let add = [Path::new("add"), Path::new("--all"), Path::new("--")];
+        git::run(
+            root,
+            add.into_iter().chain(paths.iter().map(PathBuf::as_path)),
+        )?;
+    }
+
+    let staged = git::output(
+        root,
+        ["diff", "--cached", "--name-only", "--relative", "-z"],
+    )?;
+    let staged: Vec<PathBuf> = split(&staged).collect();
+    if staged.is_empty() {
+        println!("{} nothing to commit", "✓   Commit".green().bold());
+        return Ok(());
+    }
+
+    let message = match args.message {
+        Some(message) => message,
+        None => {
+            let proposed = propose_message(&staged, &project.config);
+            if args.no_edit {
+                proposed
+            } else {
+                let (subject, body) = proposed.split_once("\n\n").unwrap_or((&proposed, ""));
+                let subject = Text::new("Commit message").with_default(subject).prompt()?;
+                if body.is_empty() {
+                    subject
+                } else {
+                    format!("{}\n\n{}", subject, body)
+                }
+            }
+        },
+    };
+    git::run(root, ["commit", "--quiet", "--message", &message])?;
+
+    println!(
+        "{} {}",
+        "✓   Commit".green().bold(),
+        message.lines().next().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// The paths of NUL separated git output
+pub(crate) fn split(output: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    output
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The generated files among the NUL separated paths of git output
+pub(crate) fn generated_files(output: &str, generated: &Generated) -> Vec<PathBuf> {
+    split(output)
+        .filter(|path| generated.contains(path))
+        .collect()
+}
+
+pub(crate) fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A commit message naming the changed dotfiles, templates and links by the file they
+/// stand for. Many files are listed in the body instead of the subject.
+fn propose_message(paths: &[PathBuf], cfg: &Config) -> String {
+    let suffixes = [cfg.template_suffix.as_str(), cfg.link_suffix.as_str()];
+    let names: BTreeSet<String> = paths
+        .iter()
+        .map(|path| {
+            let managed = path
+                .extension()
+                .is_some_and(|ext| suffixes.iter().any(|suffix| ext == *suffix));
+            let name = if managed {
+                path.with_extension("")
+            } else {
+                path.to_path_buf()
+            };
+            name.display().to_string()
+        })
+        .collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+    match names[..] {
+        [name] => format!("Update {}", name),
+        [ref init @ .., last] if names.len() <= 3 => {
+            format!("Update {} and {}", init.join(", "), last)
+        },
+        _ => {
+            let list: Vec<String> = names.iter().map(|name| format!("- {}", name)).collect();
+            format!("Update {} dotfiles\n\n{}", names.len(), list.join("\n"))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::commands::render::render;
+    use crate::filter::Filter;
+    use crate::git::test_utils::{git, global};
+
+    #[test]
+    fn test_propose_message() {
+        let cfg = Config::default();
+        let message = |paths: &[&str]| {
+            let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+            propose_message(&paths, &cfg)
+        };
+
+        assert_eq!(message(&["zsh/zshrc.ln"]), "Update zsh/zshrc");
+        assert_eq!(
+            message(&["git/gitconfig.tpl", "zsh/zshrc.ln", "git/gitconfig"]),
+            "Update git/gitconfig and zsh/zshrc"
+        );
+        assert_eq!(message(&["a.tpl", "b", "c.ln"]), "Update a, b and c");
+        assert_eq!(
+            message(&["a", "b", "c", "d"]),
+            "Update 4 dotfiles\n\n- a\n- b\n- c\n- d"
+        );
+    }
+
+    #[test]
+    fn test_commit_skips_generated_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        git(root, &["init", "--quiet"]);
+        git(root, &["config", "user.name", "sams"]);
+        git(root, &["config", "user.email", "sams@example.com"]);
+        fs::write(root.join("sams.toml"), "").unwrap();
+        fs::write(root.join(".sams-answers.toml"), "").unwrap();
+        fs::write(root.join("gitconfig.tpl"), "[user]").unwrap();
+        let project = Project::load(&global(root)).unwrap();
+        render(&project, &Filter::default(), false).unwrap();
+
+        let args = CommitArgs {
+            message: None,
+            no_edit: true,
+        };
+        commit(args.clone(), &project).unwrap();
+        assert_eq!(
+            git(root, &["log", "--format=%s"]),
+            "Update .gitignore, gitconfig and sams.toml\n"
+        );
+        assert_eq!(
+            git(root, &["ls-files"]),
+            ".gitignore\ngitconfig.tpl\nsams.toml\n"
+        );
+
+        git(root, &["add", "--force", "gitconfig"]);
+        let err = commit(args, &project).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Refusing to commit files generated by sams: gitconfig"),
+            "{}",
+            err
+        );
+    }
+}
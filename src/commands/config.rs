@@ -0,0 +1,88 @@
+use anyhow::Result;
+use colored::Colorize;
+use serde_json::Value;
+
+use crate::args::{ConfigArgs, ConfigCommand};
+use crate::project::Project;
+use crate::settings::{is_personal, Origin};
+
+pub fn config(args: ConfigArgs, project: &Project) -> Result<()> {
+    match args.command {
+        ConfigCommand::Show { origin } => {
+            for line in show(project, origin) {
+                println!("{}", line);
+            }
+        },
+    }
+    Ok(())
+}
+
+/// One `name = value` line per setting, optionally followed by its origin. The repo and the
+/// personal settings are listed separately, as they come from different sources.
+fn show(project: &Project, origin: bool) -> Vec<String> {
+    let values = &project.settings.values;
+    let (personal, repo): (Vec<_>, Vec<_>) = values.iter().partition(|(name, _)| is_personal(name));
+    let mut lines = vec![format!(
+        "{}",
+        "# Repo settings, from the repo config".dimmed()
+    )];
+    lines.extend(setting_lines(repo, origin));
+    lines.push(String::new());
+    lines.push(format!(
+        "{}",
+        "# Personal settings, from the user config, SAMS_CFG_* variables and flags".dimmed()
+    ));
+    lines.extend(setting_lines(personal, origin));
+    lines
+}
+
+fn setting_lines<'a>(
+    settings: Vec<(&'a String, &'a (Value, Origin))>,
+    origin: bool,
+) -> impl Iterator<Item = String> + 'a {
+    settings.into_iter().map(move |(name, (value, source))| {
+        let value = toml::Value::try_from(value)
+            .map(|value| value.to_string())
+            .unwrap_or_else(|_| value.to_string());
+        let line = format!("{} = {}", name.bold(), value);
+        if origin {
+            format!("{}  {}", line, format!("# {}", source).dimmed())
+        } else {
+            line
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::args::GlobalArgs;
+
+    #[test]
+    fn test_show_origin() {
+        colored::control::set_override(false);
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("sams.toml"), "link_suffix = \"link\"").unwrap();
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+
+        let lines = show(&project, true);
+        let config_path = dir.path().join("sams.toml");
+        assert!(lines.contains(&format!(
+            "link_suffix = \"link\"  # repo config {}",
+            config_path.display()
+        )));
+        assert!(show(&project, false).contains(&"link_suffix = \"link\"".to_string()));
+        let lines = show(&project, false);
+        let position = |line: &str| lines.iter().position(|other| other == line).unwrap();
+        assert!(position("link_suffix = \"link\"") < position("jobs = 1"));
+        assert!(position("jobs = 1") > position(""));
+    }
+}
@@ -6,18 +6,29 @@ use anyhow::{bail, Context, Ok, Result};
 
 use crate::args::InitArgs;
 use crate::config::{Config, DataType, Parameter};
+use crate::format::Format;
+use crate::generated::Generated;
+use crate::state::State;
 
 pub fn init(args: InitArgs) -> Result<()> {
     let cfg = default_config();
     let cfg_dir = args.dir.join(args.file);
-    let cfg_toml = toml::to_string(&cfg).context("Failed to serialize config to TOML")?;
+    let cfg_content = Format::from_path(&cfg_dir)
+        .serialize(&cfg)
+        .context("Failed to serialize config")?;
 
     if !args.no_git {
         init_git_repo(&args.dir).context("Git repository check failed")?;
     }
 
-    write_config(&cfg_dir, &cfg_toml, args.force)
-        .with_context(|| format!("Failed to initialize config file: {}", cfg_dir.display()))
+    write_config(&cfg_dir, &cfg_content, args.force)
+        .with_context(|| format!("Failed to initialize config file: {}", cfg_dir.display()))?;
+
+    // Keep the answers and later the rendered outputs out of the repository
+    if !args.no_git {
+        Generated::new(&cfg, &args.dir, &State::default()).update_gitignore(&args.dir)?;
+    }
+    Ok(())
 }
 
 fn init_git_repo(directory: &Path) -> Result<()> {
@@ -105,6 +116,13 @@ mod tests {
                 "git repo existence check failed for no_git={}",
                 no_git
             );
+            let gitignore = fs::read_to_string(temp_dir.path().join(".gitignore"));
+            assert_eq!(
+                gitignore.is_ok_and(|content| content.contains("/.sams-answers.toml\n")),
+                expect_git,
+                "gitignore check failed for no_git={}",
+                no_git
+            );
         }
     }
 
@@ -1,47 +1,374 @@
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
-
-use crate::args::GlobalArgs;
-use crate::config::{Config, Task};
-
-pub fn install(global: &GlobalArgs) -> Result<()> {
-    let cfg = Config::from_args(global)?;
-    let pb = progress_bar(cfg.tasks.len() as u64);
-
-    for (idx, task) in cfg.tasks.iter().enumerate() {
-        let name = task
-            .name
-            .as_deref()
-            .unwrap_or_else(|| task.script.to_str().unwrap());
-        pb.set_prefix(format!("{:>8} {}", "Running".yellow().bold(), name.bold()));
-
-        run_task(task, &pb, &global.root)
-            .with_context(|| format!("Failed to execute task: {}", name))?;
-
-        pb.set_message("");
-        pb.inc(1);
-        pb.println(format!(
-            "{} {:>8} {} ({}/{})",
-            "✓".green().bold(),
-            "Finished".green().bold(),
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tempfile::TempPath;
+
+use crate::config::{RunPolicy, Task};
+use crate::context::{builtin_context, read_context};
+use crate::filter::Filter;
+use crate::graph::TaskGraph;
+use crate::project::Project;
+use crate::settings::Settings;
+use crate::state::{content_hash, State};
+use crate::template::{eval_condition, render_raw_str};
+
+pub fn install(project: &Project) -> Result<()> {
+    run_tasks(&project.config.tasks, project, &Filter::default())
+}
+
+/// Run a set of tasks respecting their dependencies, conditions and run policies.
+///
+/// Tasks not selected by the filter are treated as done without any output.
+pub fn run_tasks(tasks: &[Task], project: &Project, filter: &Filter) -> Result<()> {
+    let graph = TaskGraph::new(tasks)?;
+    let keys = state_keys(tasks);
+    let state_file = project.state_file();
+    let mut state = State::load(&state_file)?;
+    let log = RunLog::new(&project.log_dir());
+
+    // Answers are only needed for conditions and templated commands. Hooks running before
+    // the first ask only get the builtin variables.
+    let uses_answers = |task: &Task| {
+        task.when.is_some()
+            || task.template
+            || task.run.is_some()
+            || !task.args.is_empty()
+            || !task.env.is_empty()
+    };
+    let ctx = if tasks.iter().any(uses_answers) && project.answer_file().exists() {
+        read_context(project.answer_file())?
+    } else {
+        builtin_context()
+    };
+
+    let total = tasks
+        .iter()
+        .filter(|task| filter.matches_task(task))
+        .count();
+    let jobs = project.settings.jobs.max(1);
+    let mp = MultiProgress::new();
+
+    let mut started = vec![false; tasks.len()];
+    let mut done = vec![false; tasks.len()];
+    let mut hashes: Vec<Option<String>> = vec![None; tasks.len()];
+    let skipped = |name: &str, reason: &str, finished: usize| {
+        mp.println(format!(
+            "{} {:>8} {} ({}) ({}/{})",
+            "-".yellow().bold(),
+            "Skipped".yellow().bold(),
             name.bold(),
-            idx + 1,
-            cfg.tasks.len()
-        ));
+            reason,
+            finished,
+            total
+        ))
+    };
+    let mut finished = 0;
+    let mut running = 0;
+    let mut error: Option<anyhow::Error> = None;
+    let (tx, rx) = mpsc::channel::<(usize, Result<()>)>();
+
+    thread::scope(|scope| -> Result<()> {
+        loop {
+            // Start as many ready tasks as allowed, unless a task already failed.
+            while error.is_none() && running < jobs {
+                let Some(idx) = graph.next_ready(&started, &done) else {
+                    break;
+                };
+                started[idx] = true;
+
+                let task = &tasks[idx];
+                if !filter.matches_task(task) {
+                    done[idx] = true;
+                    continue;
+                }
+
+                let name = task.display_name();
+                let workdir = project.resolve(&task.workdir);
+
+                if !condition_met(task, &ctx)
+                    .with_context(|| format!("Failed to check task: {}", name))?
+                {
+                    done[idx] = true;
+                    finished += 1;
+                    skipped(&name, "condition not met", finished)?;
+                    continue;
+                }
+
+                let command = TaskCommand::prepare(task, &ctx, &workdir)
+                    .with_context(|| format!("Failed to prepare task: {}", name))?;
+                hashes[idx] = command.hash.clone();
+
+                let skip = skip_reason(task, &keys[idx], command.hash.as_deref(), &state, &workdir)
+                    .with_context(|| format!("Failed to check task: {}", name))?;
+                if let Some(reason) = skip {
+                    done[idx] = true;
+                    finished += 1;
+                    skipped(&name, &reason, finished)?;
+                    continue;
+                }
+
+                let pb = mp.add(progress_bar());
+                pb.set_prefix(format!("{:>8} {}", "Running".yellow().bold(), name.bold()));
+
+                let tx = tx.clone();
+                let root = &project.root;
+                let log = &log;
+                let settings = &project.settings;
+                running += 1;
+                scope.spawn(move || {
+                    let result = run_task(task, &command, &pb, root, log, settings);
+                    pb.finish_and_clear();
+                    // The receiver only goes away once all tasks are finished.
+                    let _ = tx.send((idx, result));
+                });
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            let (idx, result) = rx.recv().context("Task runner disconnected")?;
+            running -= 1;
+
+            let task = &tasks[idx];
+            let name = task.display_name();
+            if let Err(err) = result {
+                error.get_or_insert(err.context(format!(
+                    "Failed to execute task: {} (full log: {})",
+                    name,
+                    log.path.display()
+                )));
+                continue;
+            }
+
+            if let Some(hash) = hashes[idx].take() {
+                if task.run_policy == RunPolicy::Once {
+                    state.once.insert(State::once_entry(&keys[idx], &hash));
+                } else {
+                    state.onchange.insert(keys[idx].clone(), hash);
+                }
+                state.save(&state_file)?;
+            }
+
+            done[idx] = true;
+            finished += 1;
+            mp.println(format!(
+                "{} {:>8} {} ({}/{})",
+                "✓".green().bold(),
+                "Finished".green().bold(),
+                name.bold(),
+                finished,
+                total
+            ))?;
+        }
+        Ok(())
+    })?;
+
+    match error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// The keys of the tasks in the state file. Tasks without an `id` may share a key, later
+/// ones get their position among them appended, like `install.sh#2`.
+fn state_keys(tasks: &[Task]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    tasks
+        .iter()
+        .map(|task| {
+            let key = task.key();
+            let count = seen.entry(key.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => key,
+                n => format!("{}#{}", key, n),
+            }
+        })
+        .collect()
+}
+
+/// The command of a task with all templates rendered, ready to be executed
+struct TaskCommand {
+    /// Arguments passed to the shell of the task
+    shell_args: Vec<String>,
+
+    /// Environment variables set for the command
+    env: BTreeMap<String, String>,
+
+    /// Hash identifying this version of the command, if the run policy needs one
+    hash: Option<String>,
+
+    /// Rendered script of a templated task, deleted once the command is dropped
+    _rendered_script: Option<TempPath>,
+}
+
+impl TaskCommand {
+    fn prepare(task: &Task, ctx: &tera::Context, workdir: &Path) -> Result<Self> {
+        let mut rendered_script = None;
+        let args = task
+            .args
+            .iter()
+            .map(|arg| render_raw_str(arg, ctx))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (shell_args, mut content) = match (&task.script, &task.run) {
+            (Some(script), None) => {
+                let needs_content = task.template || task.run_policy != RunPolicy::Always;
+                let mut content = if needs_content {
+                    let path = workdir.join(script);
+                    fs::read(&path)
+                        .with_context(|| format!("Failed to read script: {}", path.display()))?
+                } else {
+                    Vec::new()
+                };
+
+                let path = if task.template {
+                    let template =
+                        String::from_utf8(content).context("Script is not valid UTF-8")?;
+                    let rendered = render_raw_str(&template, ctx).with_context(|| {
+                        format!("Failed to render script: {}", script.display())
+                    })?;
+                    let temp = write_temp_script(&workdir.join(script), &rendered)?;
+                    let path = temp.to_path_buf();
+                    rendered_script = Some(temp);
+                    content = rendered.into_bytes();
+                    path
+                } else {
+                    script.clone()
+                };
+
+                for arg in &args {
+                    content.push(0);
+                    content.extend_from_slice(arg.as_bytes());
+                }
+                let mut shell_args = vec![path.display().to_string()];
+                shell_args.extend(args);
+                (shell_args, content)
+            },
+            (None, Some(run)) => {
+                let mut cmd = render_raw_str(run, ctx)?;
+                for arg in &args {
+                    cmd.push(' ');
+                    cmd.push_str(&shell_quote(arg));
+                }
+                let content = cmd.clone().into_bytes();
+                (vec!["-c".to_string(), cmd], content)
+            },
+            (Some(_), Some(_)) => bail!("A task must not define both `script` and `run`"),
+            (None, None) => bail!("A task must define either `script` or `run`"),
+        };
+
+        let env: BTreeMap<String, String> = task
+            .env
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), render_raw_str(value, ctx)?)))
+            .collect::<Result<_>>()?;
+
+        // The same command run in another directory or environment is another version
+        content.push(0);
+        content.extend_from_slice(task.workdir.as_os_str().as_encoded_bytes());
+        for (key, value) in &env {
+            content.push(0);
+            content.extend_from_slice(format!("{}={}", key, value).as_bytes());
+        }
+
+        let hash = match task.run_policy {
+            RunPolicy::Always => None,
+            RunPolicy::Once | RunPolicy::OnChange => Some(content_hash(&content)),
+        };
+
+        Ok(Self {
+            shell_args,
+            env,
+            hash,
+            _rendered_script: rendered_script,
+        })
     }
-    pb.finish_and_clear();
-    Ok(())
 }
 
-fn progress_bar(len: u64) -> ProgressBar {
-    let pb = ProgressBar::new(len).with_style(
-        ProgressStyle::with_template("{spinner:.cyan.bold} {prefix} ({pos}/{len}) {msg}")
+/// Write a rendered script next to the original, so it can find files relative to `$0`
+fn write_temp_script(script: &Path, content: &str) -> Result<TempPath> {
+    let dir = script
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut file = tempfile::Builder::new()
+        .prefix(".sams-task-")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create temporary script in: {}", dir.display()))?;
+    file.write_all(content.as_bytes())
+        .context("Failed to write temporary script")?;
+    Ok(file.into_temp_path())
+}
+
+/// Quote a string so the shell passes it on as a single word.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn condition_met(task: &Task, ctx: &tera::Context) -> Result<bool> {
+    match &task.when {
+        Some(expr) => eval_condition(expr, ctx),
+        None => Ok(true),
+    }
+}
+
+/// Returns why a task does not need to run, or `None` if it should run.
+fn skip_reason(
+    task: &Task,
+    key: &str,
+    hash: Option<&str>,
+    state: &State,
+    workdir: &Path,
+) -> Result<Option<String>> {
+    match (task.run_policy, hash) {
+        (RunPolicy::Once, Some(hash)) if state.once.contains(&State::once_entry(key, hash)) => {
+            return Ok(Some("already run".into()));
+        },
+        (RunPolicy::OnChange, Some(hash)) if state.onchange.get(key).is_some_and(|h| h == hash) => {
+            return Ok(Some("unchanged".into()));
+        },
+        _ => {},
+    }
+
+    if let Some(creates) = &task.creates {
+        if workdir.join(creates).exists() {
+            return Ok(Some(format!("{} exists", creates.display())));
+        }
+    }
+
+    if let Some(unless) = &task.unless {
+        let status = Command::new(&task.shell)
+            .arg("-c")
+            .arg(unless)
+            .current_dir(workdir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("Failed to run `unless` check")?;
+        if status.success() {
+            return Ok(Some("already satisfied".into()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn progress_bar() -> ProgressBar {
+    let pb = ProgressBar::new_spinner().with_style(
+        ProgressStyle::with_template("{spinner:.cyan.bold} {prefix} {msg}")
             .unwrap()
             .progress_chars("#>-")
             .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
@@ -50,29 +377,551 @@ fn progress_bar(len: u64) -> ProgressBar {
     pb
 }
 
-fn run_task(task: &Task, pb: &ProgressBar, root: &Path) -> Result<()> {
+/// Log file collecting the output of all tasks of a single install run
+/// The log of one install run, created when the first task starts
+struct RunLog {
+    dir: PathBuf,
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+/// Number of run logs kept in the log directory, older ones are removed
+const KEPT_RUN_LOGS: usize = 20;
+
+impl RunLog {
+    fn new(dir: &Path) -> Self {
+        // Sub-second precision keeps runs apart and the names sorted by time
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!("install-{}-{:09}.log", now.as_secs(), now.subsec_nanos());
+        Self {
+            dir: dir.to_path_buf(),
+            path: dir.join(name),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Create the log file unless it exists already, removing the oldest run logs
+    fn open(&self) -> Result<()> {
+        let Ok(mut file) = self.file.lock() else {
+            bail!("Failed to lock log file: {}", self.path.display());
+        };
+        if file.is_some() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create log directory: {}", self.dir.display()))?;
+        *file = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .with_context(|| format!("Failed to create log file: {}", self.path.display()))?,
+        );
+
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read log directory: {}", self.dir.display()))?;
+        let mut logs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("install-") && name.ends_with(".log"))
+            })
+            .collect();
+        logs.sort();
+        for old in &logs[..logs.len().saturating_sub(KEPT_RUN_LOGS)] {
+            fs::remove_file(old)
+                .with_context(|| format!("Failed to remove old log file: {}", old.display()))?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&self, task: &str, line: &str) {
+        // Logging is best effort and must never fail the task itself.
+        if let Ok(mut file) = self.file.lock() {
+            if let Some(file) = file.as_mut() {
+                let _ = writeln!(file, "[{task}] {line}");
+            }
+        }
+    }
+}
+
+fn run_task(
+    task: &Task,
+    command: &TaskCommand,
+    pb: &ProgressBar,
+    root: &Path,
+    log: &RunLog,
+    settings: &Settings,
+) -> Result<()> {
+    let name = task.display_name();
+    log.open()?;
+    // In its own process group everything the command starts can be killed with it
     let mut cmd = Command::new(&task.shell)
-        .arg(task.script.as_os_str())
+        .args(&command.shell_args)
+        .envs(&command.env)
         .current_dir(root.join(&task.workdir))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .process_group(0)
         .spawn()
         .context("Failed to start command")?;
 
-    if let Some(stdout) = cmd.stdout.take() {
-        BufReader::new(stdout)
-            .lines()
-            .map_while(Result::ok)
-            .for_each(|line| {
-                pb.set_message(line);
-                pb.tick();
-            });
+    let stdout = cmd.stdout.take().context("Failed to capture stdout")?;
+    let stderr = cmd.stderr.take().context("Failed to capture stderr")?;
+
+    // Both pipes are drained concurrently so a chatty script can never block on a full pipe.
+    // The readers are detached so a timed out command cannot keep us waiting on its output.
+    let (tx, rx) = mpsc::channel::<String>();
+    let streams: [Box<dyn Read + Send>; 2] = [Box::new(stdout), Box::new(stderr)];
+    for stream in streams {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            BufReader::new(stream)
+                .lines()
+                .map_while(Result::ok)
+                .for_each(|line| {
+                    let _ = tx.send(line);
+                });
+        });
     }
+    drop(tx);
+
+    let deadline = task
+        .timeout
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut tail: VecDeque<String> = VecDeque::with_capacity(settings.tail);
+    loop {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => {
+                log.write_line(&name, &line);
+                if settings.verbose {
+                    pb.println(format!("{} {}", format!("{name} |").dimmed(), line));
+                } else {
+                    pb.set_message(line.clone());
+                }
+
+                if settings.tail > 0 {
+                    if tail.len() == settings.tail {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
 
-    let status = cmd.wait().context("Failed to wait for command")?;
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return time_out(&mut cmd, task);
+        }
+    }
+
+    // The command may still run after closing its output
+    let status = loop {
+        if let Some(status) = cmd.try_wait().context("Failed to wait for command")? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return time_out(&mut cmd, task);
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
     if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!("Command failed with status: {}", status))
+        return Ok(());
+    }
+
+    let mut msg = format!("Command failed with status: {}", status);
+    if !tail.is_empty() {
+        msg.push_str(&format!("\n\nLast {} lines of output:", tail.len()));
+        for line in tail {
+            msg.push_str(&format!("\n  | {line}"));
+        }
+    }
+    Err(anyhow::anyhow!(msg))
+}
+
+/// Kill a timed out command and everything it started
+fn time_out(cmd: &mut Child, task: &Task) -> Result<()> {
+    let group = i32::try_from(cmd.id()).context("Invalid process id")?;
+    // SAFETY: `kill` has no memory effects, the command leads its own process group
+    if unsafe { libc::kill(-group, libc::SIGKILL) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to kill timed out command");
+    }
+    cmd.wait().context("Failed to wait for command")?;
+    bail!(
+        "Command timed out after {}s",
+        task.timeout.unwrap_or_default()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn task() -> Task {
+        Task {
+            script: Some("install.sh".into()),
+            run: None,
+            args: vec![],
+            env: BTreeMap::new(),
+            timeout: None,
+            template: false,
+            name: None,
+            id: None,
+            depends_on: vec![],
+            workdir: ".".into(),
+            shell: "sh".into(),
+            when: None,
+            creates: None,
+            unless: None,
+            run_policy: RunPolicy::Always,
+            tags: vec![],
+        }
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("exists"), "").unwrap();
+
+        let mut state = State::default();
+        state.once.insert(State::once_entry("fonts", "abc"));
+        state.onchange.insert("fonts".into(), "def".into());
+
+        let check = |task: &Task, hash: Option<&str>| {
+            skip_reason(task, "fonts", hash, &state, dir.path()).unwrap()
+        };
+
+        assert_eq!(check(&task(), None), None);
+
+        let once = Task {
+            run_policy: RunPolicy::Once,
+            ..task()
+        };
+        assert_eq!(check(&once, Some("abc")).as_deref(), Some("already run"));
+        assert_eq!(check(&once, Some("xyz")), None);
+        let other = skip_reason(&once, "other", Some("abc"), &state, dir.path()).unwrap();
+        assert_eq!(other, None);
+
+        let onchange = Task {
+            run_policy: RunPolicy::OnChange,
+            ..task()
+        };
+        assert_eq!(check(&onchange, Some("def")).as_deref(), Some("unchanged"));
+        assert_eq!(check(&onchange, Some("xyz")), None);
+
+        let creates = Task {
+            creates: Some("exists".into()),
+            ..task()
+        };
+        assert_eq!(check(&creates, None).as_deref(), Some("exists exists"));
+
+        let unless = Task {
+            unless: Some("true".into()),
+            ..task()
+        };
+        assert_eq!(check(&unless, None).as_deref(), Some("already satisfied"));
+        let unless = Task {
+            unless: Some("false".into()),
+            ..task()
+        };
+        assert_eq!(check(&unless, None), None);
+    }
+
+    #[test]
+    fn test_run_task_drains_stderr_and_reports_tail() {
+        let dir = tempdir().unwrap();
+        // Far more than a pipe buffer on stderr, then fail.
+        fs::write(
+            dir.path().join("install.sh"),
+            "i=0; while [ $i -lt 5000 ]; do echo \"line $i ........................\" >&2; \
+             i=$((i+1)); done; echo done; exit 3",
+        )
+        .unwrap();
+
+        let log = RunLog::new(&dir.path().join("logs"));
+        let settings = Settings {
+            tail: 2,
+            ..Settings::default()
+        };
+
+        let command = TaskCommand::prepare(&task(), &tera::Context::new(), dir.path()).unwrap();
+        let err = run_task(
+            &task(),
+            &command,
+            &ProgressBar::hidden(),
+            dir.path(),
+            &log,
+            &settings,
+        )
+        .unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("Last 2 lines of output:"), "{msg}");
+
+        let logged = fs::read_to_string(&log.path).unwrap();
+        assert_eq!(logged.lines().count(), 5001);
+        assert!(logged.contains("[install.sh] line 4999"));
+    }
+
+    #[test]
+    fn test_run_log_is_created_lazily_and_pruned() {
+        let dir = tempdir().unwrap();
+        let logs = dir.path().join("logs");
+        let log = RunLog::new(&logs);
+        assert_ne!(log.path, RunLog::new(&logs).path);
+        assert!(!logs.exists());
+
+        fs::create_dir(&logs).unwrap();
+        for i in 0..KEPT_RUN_LOGS + 5 {
+            fs::write(
+                logs.join(format!("install-10000000{:02}-000000000.log", i)),
+                "",
+            )
+            .unwrap();
+        }
+        fs::write(logs.join("notes.txt"), "").unwrap();
+        log.open().unwrap();
+        log.write_line("task", "line");
+
+        assert_eq!(fs::read_dir(&logs).unwrap().count(), KEPT_RUN_LOGS + 1);
+        assert!(!logs.join("install-1000000005-000000000.log").exists());
+        assert!(logs.join("install-1000000006-000000000.log").exists());
+        assert_eq!(fs::read_to_string(&log.path).unwrap(), "[task] line\n");
+    }
+
+    #[test]
+    fn test_condition_met() {
+        let mut ctx = tera::Context::new();
+        ctx.insert("os", "linux");
+
+        assert!(condition_met(&task(), &ctx).unwrap());
+        let when = Task {
+            when: Some(r#"os == "macos""#.into()),
+            ..task()
+        };
+        assert!(!condition_met(&when, &ctx).unwrap());
+    }
+
+    #[test]
+    fn test_prepare_inline_command() {
+        let mut ctx = tera::Context::new();
+        ctx.insert("editor", "nvim");
+
+        let inline = Task {
+            script: None,
+            run: Some("{{ editor }} --headless".into()),
+            args: vec!["+PlugInstall".into(), "it's".into()],
+            env: BTreeMap::from([("EDITOR".into(), "{{ editor }}".into())]),
+            run_policy: RunPolicy::OnChange,
+            ..task()
+        };
+        let command = TaskCommand::prepare(&inline, &ctx, Path::new(".")).unwrap();
+        assert_eq!(
+            command.shell_args,
+            vec!["-c", r#"nvim --headless '+PlugInstall' 'it'\''s'"#]
+        );
+        assert_eq!(command.env["EDITOR"], "nvim");
+        assert!(command.hash.is_some());
+
+        let both = Task {
+            run: Some("true".into()),
+            ..task()
+        };
+        assert!(TaskCommand::prepare(&both, &ctx, Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_state_keys() {
+        let tasks = [
+            task(),
+            task(),
+            Task {
+                name: Some("fonts".into()),
+                ..task()
+            },
+        ];
+        assert_eq!(
+            state_keys(&tasks),
+            vec!["install.sh", "install.sh#2", "fonts"]
+        );
+    }
+
+    #[test]
+    fn test_hash_depends_on_workdir_and_env() {
+        let ctx = tera::Context::new();
+        let hash = |task: &Task| {
+            TaskCommand::prepare(task, &ctx, Path::new("."))
+                .unwrap()
+                .hash
+                .unwrap()
+        };
+        let install = || Task {
+            script: None,
+            run: Some("make install".into()),
+            run_policy: RunPolicy::Once,
+            ..task()
+        };
+        let other_dir = Task {
+            workdir: "fonts".into(),
+            ..install()
+        };
+        let other_env = Task {
+            env: BTreeMap::from([("PREFIX".into(), "/opt".into())]),
+            ..install()
+        };
+
+        assert_eq!(hash(&install()), hash(&install()));
+        assert_ne!(hash(&install()), hash(&other_dir));
+        assert_ne!(hash(&install()), hash(&other_env));
+    }
+
+    #[test]
+    fn test_run_task_timeout() {
+        let dir = tempdir().unwrap();
+        let log = RunLog::new(dir.path());
+        let settings = Settings {
+            tail: 0,
+            ..Settings::default()
+        };
+        let slow = Task {
+            script: None,
+            run: Some("sleep 5".into()),
+            timeout: Some(0),
+            ..task()
+        };
+
+        let command = TaskCommand::prepare(&slow, &tera::Context::new(), dir.path()).unwrap();
+        let start = Instant::now();
+        let err = run_task(
+            &slow,
+            &command,
+            &ProgressBar::hidden(),
+            dir.path(),
+            &log,
+            &settings,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_task_timeout_kills_process_group() {
+        let dir = tempdir().unwrap();
+        let log = RunLog::new(dir.path());
+        let settings = Settings {
+            tail: 0,
+            ..Settings::default()
+        };
+        // The background sleep outlives the shell and the output is closed early
+        let detached = Task {
+            script: None,
+            run: Some("sleep 30 & echo $! > pid; exec >/dev/null 2>&1; sleep 30".into()),
+            timeout: Some(1),
+            ..task()
+        };
+
+        let command = TaskCommand::prepare(&detached, &tera::Context::new(), dir.path()).unwrap();
+        let start = Instant::now();
+        let err = run_task(
+            &detached,
+            &command,
+            &ProgressBar::hidden(),
+            dir.path(),
+            &log,
+            &settings,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // Killed processes take a moment to exit and linger as zombies until reaped
+        let pid = fs::read_to_string(dir.path().join("pid")).unwrap();
+        let exited = || {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+            let stat = stat.unwrap_or_default();
+            let state = stat
+                .rsplit(") ")
+                .next()
+                .and_then(|rest| rest.chars().next());
+            matches!(state, None | Some('Z'))
+        };
+        let start = Instant::now();
+        while !exited() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(exited(), "background process of the task is still running");
+    }
+
+    #[test]
+    fn test_templated_script_is_not_escaped() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("scripts")).unwrap();
+        fs::write(dir.path().join("scripts/data"), "").unwrap();
+        fs::write(
+            dir.path().join("scripts/install.sh"),
+            "printf '%s' \"{{ value }}\" > out; test -f \"$(dirname \"$0\")/data\"",
+        )
+        .unwrap();
+
+        let mut ctx = tera::Context::new();
+        ctx.insert("value", r#"a/b & \"c\""#);
+        let templated = Task {
+            script: Some("scripts/install.sh".into()),
+            template: true,
+            ..task()
+        };
+        let command = TaskCommand::prepare(&templated, &ctx, dir.path()).unwrap();
+        let log = RunLog::new(&dir.path().join("logs"));
+        run_task(
+            &templated,
+            &command,
+            &ProgressBar::hidden(),
+            dir.path(),
+            &log,
+            &Settings::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out")).unwrap(),
+            r#"a/b & "c""#
+        );
+    }
+
+    #[test]
+    fn test_prepare_templated_script() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("install.sh"),
+            "{{ package_manager }} install git",
+        )
+        .unwrap();
+
+        let mut ctx = tera::Context::new();
+        ctx.insert("package_manager", "apt");
+        let templated = Task {
+            template: true,
+            ..task()
+        };
+
+        let command = TaskCommand::prepare(&templated, &ctx, dir.path()).unwrap();
+        let rendered = fs::read_to_string(&command.shell_args[0]).unwrap();
+        assert_eq!(rendered, "apt install git");
+        assert_eq!(
+            Path::new(&command.shell_args[0]).parent(),
+            Some(dir.path()),
+            "rendered script should be next to the original"
+        );
+
+        let path = PathBuf::from(&command.shell_args[0]);
+        drop(command);
+        assert!(!path.exists(), "rendered script should be cleaned up");
     }
 }
@@ -1,31 +1,87 @@
 use std::fs;
-use std::os::unix::fs as unix_fs;
-use std::path::PathBuf;
+use std::path::{self, Path, PathBuf};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
-use ignore::DirEntry;
 
-use crate::args::GlobalArgs;
-use crate::config::Config;
 use crate::context::read_context;
+use crate::filter::Filter;
+use crate::project::Project;
 use crate::template::render_template_str;
 use crate::walk::WalkOptions;
+use crate::{atomic, errors, parallel};
 
-pub fn link(global: &GlobalArgs) -> Result<()> {
-    let cfg = Config::from_args(global).context("Failed to load configuration")?;
-    let ctx = read_context(&cfg.answer_file).context("Failed to read context file")?;
+/// Link all files, returning the linked files (without suffix) whose link was newly created.
+///
+/// Failing files do not stop the others from being linked unless `fail_fast` is set, all
+/// errors are returned together at the end.
+pub fn link(project: &Project, filter: &Filter, fail_fast: bool) -> Result<Vec<PathBuf>> {
+    let cfg = &project.config;
+    let ctx = read_context(project.answer_file()).context("Failed to read context file")?;
 
-    WalkOptions::from_config(&cfg)
-        .with_extension(cfg.link_suffix)
-        .walk(&global.root)
+    let mut errors = Vec::new();
+    let mut sources = Vec::new();
+    for entry in WalkOptions::from_config(cfg)
+        .with_extension(cfg.link_suffix.clone())
+        .with_filter(filter.clone())
+        .walk(&project.root)
         .context("Failed to walk directory")?
-        .skip(1)
-        .try_for_each(|entry| process_entry(&entry?, &ctx))
+    {
+        match entry {
+            Ok(entry) => sources.push(entry.into_path()),
+            Err(err) => errors.push(anyhow::Error::new(err).context("Failed to walk directory")),
+        }
+    }
+    sources.sort();
+
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    let mut failed = Vec::new();
+    let results = parallel::try_map(&sources, fail_fast, |src| link_file(src, &ctx));
+    for (src, result) in sources.iter().zip(results) {
+        match result {
+            Some(Ok((dst, created))) => {
+                println!(
+                    "{} {} -> {}",
+                    "✓  Linking".bold().green(),
+                    src.display(),
+                    dst.display()
+                );
+                if created {
+                    changed.push(src.with_extension(""));
+                } else {
+                    unchanged += 1;
+                }
+            },
+            Some(Err(err)) => {
+                failed.push(src);
+                errors.push(err);
+            },
+            None => {},
+        }
+    }
+    for src in failed {
+        println!("{} {}", "✗  Linking".bold().red(), src.display());
+    }
+    println!(
+        "{} {} created, {} unchanged, {} failed",
+        "     Linked".bold(),
+        changed.len(),
+        unchanged,
+        errors.len()
+    );
+
+    if fail_fast {
+        errors.truncate(1);
+    }
+    errors::combine(errors, "link")?;
+
+    Ok(changed)
 }
 
-fn process_entry(entry: &DirEntry, ctx: &tera::Context) -> Result<()> {
-    let src = entry.path();
+/// Link the file to its rendered destination, returning the destination and whether the
+/// link was newly created.
+fn link_file(src: &Path, ctx: &tera::Context) -> Result<(PathBuf, bool)> {
     let dst = PathBuf::from(
         render_template_str(
             src.with_extension("")
@@ -33,20 +89,18 @@ fn process_entry(entry: &DirEntry, ctx: &tera::Context) -> Result<()> {
                 .context("Source path is not valid UTF-8")?,
             ctx,
         )
-        .context("Failed to render template")?,
+        .with_context(|| format!("Failed to render link destination: {}", src.display()))?,
     );
 
-    if dst.exists() {
-        fs::remove_file(&dst).context("Failed to remove existing file at destination")?;
-    }
-    unix_fs::symlink(src, &dst).context("Failed to create symbolic link")?;
+    // The link has to work no matter where it is placed or from where the root was given
+    let target = path::absolute(src)
+        .with_context(|| format!("Failed to resolve path: {}", src.display()))?;
+    let changed = fs::read_link(&dst).map_or(true, |existing| existing != target);
 
-    println!(
-        "{} {} -> {}",
-        "✓  Linking".bold().green(),
-        src.display(),
-        dst.display()
-    );
+    if changed {
+        atomic::symlink(&target, &dst)
+            .with_context(|| format!("Failed to create symbolic link: {}", dst.display()))?;
+    }
 
-    Ok(())
+    Ok((dst, changed))
 }
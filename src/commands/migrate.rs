@@ -0,0 +1,150 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use toml::Table;
+use toml_edit::DocumentMut;
+
+use crate::args::{GlobalArgs, MigrateConfigArgs};
+use crate::atomic;
+use crate::config::{supported_version, Config, CONFIG_VERSION};
+use crate::format::Format;
+use crate::project::Project;
+
+/// Updates a config document from one version of the format to the next
+type Migration = fn(&mut DocumentMut) -> Result<()>;
+
+/// The migration at index `i` updates a config from version `i + 1` to version `i + 2`.
+/// A breaking change of `Config`, `Parameter` or `Task` bumps [`CONFIG_VERSION`] and adds
+/// a migration here, so existing configs keep working after `sams migrate-config`.
+const MIGRATIONS: [Migration; 0] = [];
+
+const _: () = assert!(MIGRATIONS.len() as u32 + 1 == CONFIG_VERSION);
+
+/// Update the config file to the current version of the format, keeping comments and layout
+pub fn migrate_config(args: MigrateConfigArgs, global: &GlobalArgs) -> Result<()> {
+    let (_, config_path) = Project::locate(global)?;
+    let contents = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file at '{}'", config_path.display()))?;
+
+    let format = Format::from_path(&config_path);
+    let (version, migrated) = migrate(&contents, format).with_context(|| {
+        format!(
+            "Failed to migrate config file at '{}'",
+            config_path.display()
+        )
+    })?;
+    // Keys which were never valid are not migrated, but have to be fixed by hand
+    Config::parse(&migrated, format).with_context(|| {
+        format!(
+            "The config file at '{}' is invalid even after migrating it, it was not changed",
+            config_path.display()
+        )
+    })?;
+
+    if args.dry_run {
+        print!("{}", migrated);
+        return Ok(());
+    }
+    if migrated == contents {
+        println!(
+            "{} {} is already at version {}",
+            "✓   Migrate".bold().green(),
+            config_path.display(),
+            CONFIG_VERSION
+        );
+        return Ok(());
+    }
+
+    atomic::write(&config_path, &migrated)?;
+    println!(
+        "{} {} from version {} to {}",
+        "✓   Migrate".bold().green(),
+        config_path.display(),
+        version,
+        CONFIG_VERSION
+    );
+    Ok(())
+}
+
+/// Apply all migrations the config needs, returning its original version and the
+/// migrated config.
+///
+/// Migrations work on TOML documents, YAML and JSON configs are converted to TOML and back,
+/// which does not keep their layout, so they are only rewritten if they need a migration.
+fn migrate(contents: &str, format: Format) -> Result<(u32, String)> {
+    let version = supported_version(contents, format)?;
+
+    let explicit = format.parse::<Table>(contents)?.contains_key("version");
+    if format != Format::Toml && explicit && version == CONFIG_VERSION {
+        return Ok((version, contents.to_string()));
+    }
+
+    let mut doc: DocumentMut = match format {
+        Format::Toml => contents.parse(),
+        _ => toml::to_string(&format.parse::<Table>(contents)?)?.parse(),
+    }
+    .context("Failed to parse config")?;
+    for migration in &MIGRATIONS[(version.max(1) - 1) as usize..] {
+        migration(&mut doc)?;
+    }
+    doc["version"] = toml_edit::value(i64::from(CONFIG_VERSION));
+
+    let migrated = match format {
+        Format::Toml => doc.to_string(),
+        _ => format.serialize(&toml::from_str::<Table>(&doc.to_string())?)?,
+    };
+    Ok((version, migrated))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_adds_version() {
+        let contents =
+            "# My dotfiles\nanswer_file = \"answers.toml\"\n\n[[tasks]]\nrun = \"true\"\n";
+        let (version, migrated) = migrate(contents, Format::Toml).unwrap();
+
+        assert_eq!(version, 1);
+        assert_eq!(
+            migrated,
+            "# My dotfiles\nanswer_file = \"answers.toml\"\nversion = 1\n\n[[tasks]]\nrun = \
+             \"true\"\n"
+        );
+        assert_eq!(migrate(&migrated, Format::Toml).unwrap().1, migrated);
+        assert!(Config::parse(&migrated, Format::Toml).is_ok());
+
+        let (_, migrated) = migrate("{\"answer_file\": \"answers.json\"}", Format::Json).unwrap();
+        let config = Config::parse(&migrated, Format::Json).unwrap();
+        assert_eq!(config.answer_file.to_str(), Some("answers.json"));
+        assert_eq!(migrate(&migrated, Format::Json).unwrap().1, migrated);
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_version() {
+        let err = migrate("version = 2", Format::Toml).unwrap_err();
+        assert!(err.to_string().contains("please update sams"), "{}", err);
+    }
+
+    #[test]
+    fn test_migrate_config_keeps_invalid_config() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("sams.toml");
+        fs::write(&config_path, "answer_fiel = \"answers.toml\"\n").unwrap();
+        let global = GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        };
+
+        let err = migrate_config(MigrateConfigArgs { dry_run: false }, &global).unwrap_err();
+        assert!(err.to_string().contains("it was not changed"), "{}", err);
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "answer_fiel = \"answers.toml\"\n"
+        );
+    }
+}
@@ -1,9 +1,16 @@
+pub mod answers;
 pub mod ask;
+pub mod check;
 pub mod clone;
+pub mod commit;
+pub mod config;
 pub mod init;
 pub mod install;
 pub mod jsonschema;
 pub mod link;
+pub mod migrate;
 pub mod pull;
+pub mod push;
 pub mod render;
 pub mod sync;
+pub mod watch;
@@ -1,15 +1,310 @@
-use std::process::Command;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
 
-use crate::args::GlobalArgs;
+use crate::args::{GlobalArgs, InstallArgs, Phase, PullArgs, SyncArgs};
+use crate::commands::ask::ask_missing;
+use crate::commands::sync::sync;
+use crate::git;
+use crate::project::Project;
 
-pub fn pull(global: &GlobalArgs) -> Result<()> {
-    Command::new("git")
-        .arg("pull")
-        .current_dir(&global.root)
-        .spawn()?
-        .wait()?;
+/// What a file changed by the incoming commits is to sams
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Config,
+    Template,
+    Link,
+    Script,
+}
+
+impl Kind {
+    /// The sync phases which have to run again when a file of this kind changes
+    fn phases(self) -> &'static [Phase] {
+        match self {
+            Kind::Config => &[Phase::Link, Phase::Render, Phase::Install],
+            Kind::Template => &[Phase::Render],
+            Kind::Link => &[Phase::Link],
+            Kind::Script => &[Phase::Install],
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Kind::Config => write!(f, "config"),
+            Kind::Template => write!(f, "template"),
+            Kind::Link => write!(f, "link"),
+            Kind::Script => write!(f, "script"),
+        }
+    }
+}
+
+/// Fast-forward the repository to its upstream branch and sync what the incoming commits
+/// changed.
+///
+/// A dirty working tree or a diverged history are refused, so pulling never needs a merge.
+/// The config is only read after the merge, so a broken config can be fixed by pulling.
+pub fn pull(args: PullArgs, global: &GlobalArgs) -> Result<()> {
+    let (root, _) = Project::locate(global)?;
+    let root = root.as_path();
+
+    let dirty = git::output(root, ["status", "--porcelain", "--untracked-files=no"])?;
+    if !dirty.is_empty() {
+        bail!(
+            "'{}' has uncommitted changes, commit or stash them before pulling:\n{}",
+            root.display(),
+            dirty.trim_end()
+        );
+    }
+
+    git::run(root, ["fetch", "--quiet"])?;
+    let upstream = git::output(
+        root,
+        [
+            "rev-parse",
+            "--abbrev-ref",
+            "--symbolic-full-name",
+            "@{upstream}",
+        ],
+    )
+    .context("The current branch has no upstream branch to pull from")?;
+    let upstream = upstream.trim();
+
+    let counts = git::output(
+        root,
+        ["rev-list", "--left-right", "--count", "HEAD...@{upstream}"],
+    )?;
+    let (local, incoming) = parse_counts(&counts)?;
+    if incoming == 0 {
+        println!(
+            "{} {} is up to date with {}",
+            "✓     Pull".green().bold(),
+            root.display(),
+            upstream
+        );
+        return Ok(());
+    }
+    if local > 0 {
+        bail!(
+            "The local branch and {} have diverged with {} local and {} incoming commits, \
+             merge or rebase them by hand",
+            upstream,
+            local,
+            incoming
+        );
+    }
+
+    // Summary of the incoming changes
+    println!(
+        "{} {} commits from {}",
+        "    Incoming".bold(),
+        incoming,
+        upstream
+    );
+    let log = git::output(
+        root,
+        ["log", "--oneline", "--no-decorate", "HEAD..@{upstream}"],
+    )?;
+    for line in log.lines() {
+        println!("      {}", line);
+    }
+    let diff = git::output(
+        root,
+        ["diff", "--name-only", "--relative", "HEAD", "@{upstream}"],
+    )?;
+
+    git::run(root, ["merge", "--ff-only", "--quiet", "@{upstream}"])?;
+    println!(
+        "{} {} to {}",
+        "✓     Pull".green().bold(),
+        root.display(),
+        upstream
+    );
+
+    // The config may have changed, so the changes are judged by the new one
+    let project = Project::load(global)?;
+    let touched: Vec<(PathBuf, Kind)> = diff
+        .lines()
+        .filter_map(|path| {
+            let path = PathBuf::from(path);
+            classify(&project, &path).map(|kind| (path, kind))
+        })
+        .collect();
+    for (path, kind) in &touched {
+        println!("{} {} ({})", "     Changed".bold(), path.display(), kind);
+    }
+
+    let answered = ask_missing(&args.answers, &project)?;
+    let mut phases = Vec::new();
+    let kinds = touched.iter().map(|(_, kind)| *kind);
+    for phase in kinds.flat_map(Kind::phases) {
+        if !phases.contains(phase) {
+            phases.push(*phase);
+        }
+    }
+    if !answered.is_empty() && !phases.contains(&Phase::Render) {
+        phases.push(Phase::Render);
+    }
+    if phases.is_empty() {
+        return Ok(());
+    }
+
+    // Rendering skips templates whose inputs did not change, so only changed templates and
+    // templates using new answers are written
+    sync(
+        SyncArgs {
+            ask: false,
+            paths: Vec::new(),
+            only: phases,
+            skip: Vec::new(),
+            tags: Vec::new(),
+            fail_fast: false,
+            answers: args.answers,
+            install: InstallArgs::default(),
+        },
+        &project,
+    )
+}
+
+/// The number of local and incoming commits printed by `git rev-list --left-right --count`
+fn parse_counts(output: &str) -> Result<(usize, usize)> {
+    let counts: Vec<usize> = output
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Unexpected commit counts: '{}'", output.trim()))?;
+    match counts[..] {
+        [local, incoming] => Ok((local, incoming)),
+        _ => bail!("Unexpected commit counts: '{}'", output.trim()),
+    }
+}
+
+/// The kind of a root relative path, `None` if it is not managed by sams
+fn classify(project: &Project, path: &Path) -> Option<Kind> {
+    let cfg = &project.config;
+    let absolute = project.resolve(path);
+    let extension = path.extension().and_then(|ext| ext.to_str());
+
+    let config_name = project.config_path.file_name();
+    if project.files.contains(&absolute) || path.file_name() == config_name {
+        Some(Kind::Config)
+    } else if extension == Some(cfg.template_suffix.as_str()) {
+        Some(Kind::Template)
+    } else if extension == Some(cfg.link_suffix.as_str()) {
+        Some(Kind::Link)
+    } else if cfg.tasks.iter().any(|task| {
+        task.script
+            .as_ref()
+            .is_some_and(|script| project.resolve(&task.workdir).join(script) == absolute)
+    }) {
+        Some(Kind::Script)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::args::AnswerArgs;
+    use crate::git::test_utils::{commit, git, global, init};
+
+    /// An origin repository and a clone of it
+    fn repos() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let dir = tempdir().unwrap();
+        let origin = init(&dir.path().join("origin"));
+        let clone = dir.path().join("clone");
+        git(
+            dir.path(),
+            &["clone", "--quiet", "origin", clone.to_str().unwrap()],
+        );
+        (dir, origin, clone)
+    }
+
+    #[test]
+    fn test_pull_renders_incoming_templates() {
+        let (_dir, origin, clone) = repos();
+        pull(PullArgs::default(), &global(&clone)).unwrap();
+
+        commit(&origin, "gitconfig.tpl", "[user]");
+        pull(PullArgs::default(), &global(&clone)).unwrap();
+        assert_eq!(
+            fs::read_to_string(clone.join("gitconfig")).unwrap(),
+            "[user]"
+        );
+    }
+
+    #[test]
+    fn test_pull_answers_new_parameters_and_fixes_broken_config() {
+        let (_dir, origin, clone) = repos();
+        commit(&origin, "sams.toml", "answer_fiel = \"answers.toml\"");
+        git(&clone, &["pull", "--quiet"]);
+        assert!(Project::load(&global(&clone)).is_err());
+
+        commit(
+            &origin,
+            "sams.toml",
+            "[[parameters]]\nkind = \"text\"\nname = \"email\"",
+        );
+        commit(&origin, "gitconfig.tpl", "email = {{ email }}");
+        let args = PullArgs {
+            answers: AnswerArgs {
+                answers: vec![("email".into(), "me@example.com".into())],
+                answers_file: None,
+                non_interactive: true,
+            },
+        };
+        pull(args, &global(&clone)).unwrap();
+        assert_eq!(
+            fs::read_to_string(clone.join("gitconfig")).unwrap(),
+            "email = me@example.com"
+        );
+    }
+
+    #[test]
+    fn test_pull_refuses_dirty_and_diverged() {
+        let (_dir, origin, clone) = repos();
+        commit(&origin, "zshrc", "export A=1");
+
+        fs::write(clone.join("sams.toml"), "log_dir = \"logs\"").unwrap();
+        let err = pull(PullArgs::default(), &global(&clone)).unwrap_err();
+        assert!(err.to_string().contains("uncommitted changes"), "{}", err);
+
+        git(&clone, &["commit", "--quiet", "--all", "-m", "logs"]);
+        let err = pull(PullArgs::default(), &global(&clone)).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("diverged with 1 local and 1 incoming commits"),
+            "{}",
+            err
+        );
+        assert!(!clone.join("zshrc").exists());
+    }
+
+    #[test]
+    fn test_classify() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("sams.toml"),
+            "[[tasks]]\nscript = \"install.sh\"\nworkdir = \"fonts\"",
+        )
+        .unwrap();
+        let project = Project::load(&global(dir.path())).unwrap();
 
-    Ok(())
+        let kind = |path: &str| classify(&project, Path::new(path));
+        assert_eq!(kind("sams.toml"), Some(Kind::Config));
+        assert_eq!(kind("nvim/sams.toml"), Some(Kind::Config));
+        assert_eq!(kind("git/gitconfig.tpl"), Some(Kind::Template));
+        assert_eq!(kind("zsh/zshrc.ln"), Some(Kind::Link));
+        assert_eq!(kind("fonts/install.sh"), Some(Kind::Script));
+        assert_eq!(kind("install.sh"), None);
+        assert_eq!(kind("README.md"), None);
+    }
 }
@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+use colored::Colorize;
+
+use crate::commands::commit::display_paths;
+use crate::generated::Generated;
+use crate::git;
+use crate::project::Project;
+
+/// Push the repository, unless files generated by sams are tracked below the root
+pub fn push(project: &Project) -> Result<()> {
+    let root = &project.root;
+    let generated = Generated::load(project)?;
+
+    let tracked = generated.tracked(root)?;
+    if !tracked.is_empty() {
+        bail!(
+            "Refusing to push files generated by sams: {}\nhelp: remove them from the \
+             repository with `git rm --cached` and commit",
+            display_paths(&tracked)
+        );
+    }
+
+    git::run(root, ["push", "--quiet"])?;
+    println!("{} {}", "✓     Push".green().bold(), root.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::git::test_utils::{commit, git, global};
+
+    #[test]
+    fn test_push_refuses_tracked_answers() {
+        let dir = tempdir().unwrap();
+        let clone = dir.path().join("clone");
+        git(dir.path(), &["init", "--quiet", "--bare", "origin.git"]);
+        git(
+            dir.path(),
+            &["clone", "--quiet", "origin.git", clone.to_str().unwrap()],
+        );
+        commit(&clone, "sams.toml", "");
+        let project = Project::load(&global(&clone)).unwrap();
+
+        git(
+            &clone,
+            &["push", "--quiet", "--set-upstream", "origin", "HEAD"],
+        );
+        push(&project).unwrap();
+
+        commit(&clone, ".sams-answers.toml", "");
+        let err = push(&project).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Refusing to push files generated by sams: .sams-answers.toml"),
+            "{}",
+            err
+        );
+    }
+}
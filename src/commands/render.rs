@@ -1,36 +1,271 @@
+use std::path::{Path, PathBuf};
+use std::{fs, mem};
+
 use anyhow::{Context, Result};
 use colored::Colorize;
+use tera::Tera;
 
-use crate::args::GlobalArgs;
-use crate::config::Config;
 use crate::context::read_context;
-use crate::template::render_template;
+use crate::filter::Filter;
+use crate::generated::{in_git_repo, Generated};
+use crate::project::Project;
+use crate::state::{content_hash, State};
+use crate::template::{compile_templates, parse_template, referenced_context};
 use crate::walk::WalkOptions;
+use crate::{atomic, errors, parallel};
+
+/// Outcome of rendering a single template
+#[derive(Debug, PartialEq)]
+enum Status {
+    /// The output was written
+    Updated,
+    /// The output already had the rendered content
+    Unchanged,
+}
+
+/// A template file below the root
+struct Source {
+    path: PathBuf,
+
+    /// The root relative path, used as name of the template and as key in the state
+    name: String,
+
+    content: String,
+}
+
+/// Render all templates, returning the outputs whose content changed.
+///
+/// Failing templates do not stop the others from being rendered unless `fail_fast` is set,
+/// all errors are returned together at the end.
+pub fn render(project: &Project, filter: &Filter, fail_fast: bool) -> Result<Vec<PathBuf>> {
+    let cfg = &project.config;
+    let ctx = read_context(project.answer_file())?;
+    let state_file = project.state_file();
+    let mut state = State::load(&state_file)?;
+
+    // All templates are compiled, so the selected ones can include any of them
+    let mut errors = Vec::new();
+    let mut paths = Vec::new();
+    for entry in WalkOptions::from_config(cfg)
+        .with_extension(cfg.template_suffix.clone())
+        .walk(&project.root)
+        .context("Failed to walk directory")?
+    {
+        match entry {
+            Ok(entry) => paths.push(entry.into_path()),
+            Err(err) => errors.push(anyhow::Error::new(err).context("Failed to walk directory")),
+        }
+    }
+    paths.sort();
+
+    let mut sources = Vec::new();
+    let mut failed = Vec::new();
+    for (path, source) in paths.iter().zip(parallel::map(&paths, |path| {
+        read_source(path, &project.root)
+            .and_then(|source| parse_template(&source.name, &source.content).map(|_| source))
+    })) {
+        match source {
+            Ok(source) => sources.push(source),
+            // Broken templates outside of the filter only matter if they are included
+            Err(err) if filter.matches_file(path.strip_prefix(&project.root).unwrap_or(path)) => {
+                failed.push(path.clone());
+                errors.push(err);
+            },
+            Err(_) => {},
+        }
+    }
+    if fail_fast && !errors.is_empty() {
+        return Err(errors.remove(0));
+    }
+    let (tera, uncompiled) = compile_templates(
+        sources
+            .iter()
+            .map(|s| (s.name.as_str(), s.content.as_str())),
+    )?;
+    for (name, err) in uncompiled {
+        if filter.matches_file(Path::new(&name)) {
+            failed.push(project.resolve(Path::new(&name)));
+            errors.push(err);
+        }
+        sources.retain(|source| source.name != name);
+    }
+    if fail_fast && !errors.is_empty() {
+        return Err(errors.remove(0));
+    }
+
+    let selected: Vec<&Source> = sources
+        .iter()
+        .filter(|source| filter.matches_file(Path::new(&source.name)))
+        .collect();
+    let results = parallel::try_map(&selected, fail_fast, |source| {
+        render_file(
+            source,
+            &sources,
+            &tera,
+            &ctx,
+            state.renders.get(&source.name),
+        )
+    });
+
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for (source, result) in selected.iter().zip(results) {
+        let Some(result) = result else {
+            continue;
+        };
+        let (status, hash) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                failed.push(source.path.clone());
+                errors.push(err);
+                continue;
+            },
+        };
+        let output = source.path.with_extension("");
+        let label = match status {
+            Status::Updated => "updated".green(),
+            Status::Unchanged => "unchanged".dimmed(),
+        };
+        println!(
+            "{} {} -> {} ({})",
+            "✓   Render".green().bold(),
+            source.path.display(),
+            output.display(),
+            label
+        );
+
+        state.renders.insert(source.name.clone(), hash);
+        match status {
+            Status::Updated => changed.push(output),
+            Status::Unchanged => unchanged += 1,
+        }
+    }
+    for path in &failed {
+        println!("{} {}", "✗   Render".red().bold(), path.display());
+    }
+    println!(
+        "{} {} updated, {} unchanged, {} failed",
+        "    Rendered".bold(),
+        changed.len(),
+        unchanged,
+        errors.len()
+    );
+
+    // Forget templates which no longer exist, but remember their outputs until they are gone
+    if errors.is_empty() && filter.is_empty() {
+        let (renders, deleted) = mem::take(&mut state.renders)
+            .into_iter()
+            .partition(|(name, _)| sources.iter().any(|s| &s.name == name));
+        state.renders = renders;
+        state
+            .stale
+            .extend(deleted.into_keys().map(|name| output_name(&name)));
+    }
+    let rendered: Vec<String> = state.renders.keys().map(|name| output_name(name)).collect();
+    state.stale.retain(|output| {
+        !rendered.contains(output)
+            && project
+                .resolve(Path::new(output))
+                .symlink_metadata()
+                .is_ok()
+    });
+    state.save(&state_file)?;
+    if in_git_repo(&project.root) {
+        Generated::new(cfg, &project.root, &state).update_gitignore(&project.root)?;
+    }
+    if fail_fast {
+        errors.truncate(1);
+    }
+    errors::combine(errors, "render")?;
 
-pub fn render(global: &GlobalArgs) -> Result<()> {
-    let cfg = Config::from_args(global)?;
-    let ctx = read_context(&cfg.answer_file)?;
-
-    let walker = WalkOptions::from_config(&cfg)
-        .with_extension(cfg.template_suffix)
-        .walk(&global.root)
-        .context("Failed to walk directory")?;
-
-    // Skip the root entry and render each subsequent entry.
-    walker
-        .skip(1)
-        .filter_map(|entry| entry.context("Failed to read directory entry").ok())
-        .try_for_each(|entry| {
-            println!(
-                "{} {} -> {}",
-                "✓   Render".green().bold(),
-                entry.path().display(),
-                entry.path().with_extension("").display()
-            );
-            render_template(entry.path(), &entry.path().with_extension(""), &ctx)
-        })?;
-
-    Ok(())
+    Ok(changed)
+}
+
+/// The root relative output of a root relative template
+fn output_name(name: &str) -> String {
+    Path::new(name)
+        .with_extension("")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn read_source(path: &Path, root: &Path) -> Result<Source> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read template: {}", path.display()))?;
+    let name = path
+        .strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string();
+    Ok(Source {
+        path: path.to_path_buf(),
+        name,
+        content,
+    })
+}
+
+/// Render a template unless neither it, its partials, the answers they refer to nor its
+/// output changed since the last render, and write the output only if its content differs.
+///
+/// Returns the status and the hash to record for the next render.
+fn render_file(
+    source: &Source,
+    sources: &[Source],
+    tera: &Tera,
+    ctx: &tera::Context,
+    recorded: Option<&String>,
+) -> Result<(Status, String)> {
+    let mut text = String::new();
+    let mut inputs = String::new();
+    for partial in partials(source, sources) {
+        text.push_str(&partial.content);
+        inputs.push_str(&format!("{}\0{}\0", partial.name, partial.content));
+    }
+    inputs.push_str(&referenced_context(&text, ctx).to_string());
+
+    let output = source.path.with_extension("");
+    let existing = fs::read(&output).ok();
+    if let Some(existing) = &existing {
+        let hash = render_hash(&inputs, existing);
+        if recorded == Some(&hash) {
+            return Ok((Status::Unchanged, hash));
+        }
+    }
+
+    let rendered = tera
+        .render(&source.name, ctx)
+        .with_context(|| format!("Failed to render template: {}", source.name))?;
+    let status = if existing.as_deref() == Some(rendered.as_bytes()) {
+        Status::Unchanged
+    } else {
+        atomic::write(&output, &rendered)
+            .with_context(|| format!("Failed to write output: {}", output.display()))?;
+        Status::Updated
+    };
+
+    Ok((status, render_hash(&inputs, rendered.as_bytes())))
+}
+
+/// The template itself and all templates it may include, directly or through other templates.
+///
+/// A template is considered included if its name occurs in an included template.
+fn partials<'a>(source: &'a Source, sources: &'a [Source]) -> Vec<&'a Source> {
+    let mut included = vec![source];
+    let mut idx = 0;
+    while let Some(current) = included.get(idx) {
+        let found: Vec<&Source> = sources
+            .iter()
+            .filter(|s| current.content.contains(&s.name))
+            .filter(|s| !included.iter().any(|i| i.name == s.name))
+            .collect();
+        included.extend(found);
+        idx += 1;
+    }
+    included
+}
+
+fn render_hash(inputs: &str, output: &[u8]) -> String {
+    content_hash(&[inputs.as_bytes(), output].concat())
 }
 
 #[cfg(test)]
@@ -42,6 +277,7 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
+    use crate::args::GlobalArgs;
 
     fn create_temp_dir() -> TempDir {
         TempDir::new().expect("Failed to create temp directory")
@@ -80,6 +316,14 @@ mod tests {
         write_file(template_path, content);
     }
 
+    fn load_project(root: &Path, config_path: &Path) -> Project {
+        Project::load(&GlobalArgs {
+            root: Some(root.to_path_buf()),
+            config_path: config_path.to_path_buf(),
+        })
+        .expect("Failed to load project")
+    }
+
     fn read_file_contents(file_path: &Path) -> String {
         fs::read_to_string(file_path).expect("Failed to read file")
     }
@@ -113,11 +357,8 @@ mod tests {
         create_template(&template_in_excluded, "key={{ key }}");
 
         // Run render function
-        let global_args = GlobalArgs {
-            root: tmp_path.to_path_buf(),
-            config_path: config_path.clone(),
-        };
-        render(&global_args).expect("Render function failed");
+        let project = load_project(tmp_path, &config_path);
+        render(&project, &Filter::default(), false).expect("Render function failed");
 
         // Assertions
         assert_eq!(
@@ -131,6 +372,135 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_nested_templates_and_path_filter() {
+        let tmp_dir = create_temp_dir();
+        let tmp_path = tmp_dir.path();
+
+        let config_path = tmp_path.join("config.toml");
+        let answer_path = tmp_path.join("answers.toml");
+        setup_config(&config_path, &answer_path, "tera", vec![]);
+        setup_answers(&answer_path, r#"key = "testvalue""#);
+
+        fs::create_dir_all(tmp_path.join("nvim/lua")).expect("Failed to create directory");
+        fs::create_dir(tmp_path.join("zsh")).expect("Failed to create directory");
+        create_template(&tmp_path.join("nvim/lua/init.lua.tera"), "{{ key }}");
+        create_template(&tmp_path.join("zsh/zshrc.tera"), "{{ key }}");
+
+        let project = load_project(tmp_path, &config_path);
+        let filter = Filter::new(&[tmp_path.join("nvim")], &[], tmp_path, &project.config).unwrap();
+        render(&project, &filter, false).expect("Render function failed");
+
+        assert_eq!(
+            read_file_contents(&tmp_path.join("nvim/lua/init.lua")),
+            "testvalue"
+        );
+        assert!(!tmp_path.join("zsh/zshrc").exists());
+    }
+
+    #[test]
+    fn test_incremental_rendering() {
+        let tmp_dir = create_temp_dir();
+        let tmp_path = tmp_dir.path();
+
+        let config_path = tmp_path.join("config.toml");
+        let answer_path = tmp_path.join("answers.toml");
+        let output = tmp_path.join("test.txt");
+        setup_config(&config_path, &answer_path, "tera", vec![]);
+        setup_answers(&answer_path, r#"key = "a""#);
+        create_template(&tmp_path.join("test.txt.tera"), "key={{ key }}");
+
+        let project = load_project(tmp_path, &config_path);
+        let filter = Filter::default();
+        assert_eq!(
+            render(&project, &filter, false).unwrap(),
+            vec![output.clone()]
+        );
+        assert!(render(&project, &filter, false).unwrap().is_empty());
+
+        // Answers the template does not refer to do not matter
+        setup_answers(&answer_path, "key = \"a\"\nother = 1");
+        assert!(render(&project, &filter, false).unwrap().is_empty());
+
+        // Manual edits of the output are overwritten
+        write_file(&output, "edited");
+        assert_eq!(
+            render(&project, &filter, false).unwrap(),
+            vec![output.clone()]
+        );
+        assert_eq!(read_file_contents(&output), "key=a");
+
+        setup_answers(&answer_path, r#"key = "b""#);
+        assert_eq!(
+            render(&project, &filter, false).unwrap(),
+            vec![output.clone()]
+        );
+        assert_eq!(read_file_contents(&output), "key=b");
+    }
+
+    #[test]
+    fn test_partials_and_aggregated_errors() {
+        let tmp_dir = create_temp_dir();
+        let tmp_path = tmp_dir.path();
+
+        let config_path = tmp_path.join("config.toml");
+        let answer_path = tmp_path.join("answers.toml");
+        setup_config(&config_path, &answer_path, "tera", vec![]);
+        setup_answers(&answer_path, r#"key = "a""#);
+        create_template(&tmp_path.join("partial.tera"), "key={{ key }}");
+        create_template(
+            &tmp_path.join("main.tera"),
+            r#"{% include "partial.tera" %}!"#,
+        );
+        create_template(&tmp_path.join("broken1.tera"), "{{ invalid syntax }}");
+        create_template(&tmp_path.join("broken2.tera"), "{% if %}");
+
+        let project = load_project(tmp_path, &config_path);
+        let err = render(&project, &Filter::default(), false).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("Failed to render 2 files"), "{}", message);
+        assert!(message.contains("broken1.tera") && message.contains("broken2.tera"));
+        assert_eq!(read_file_contents(&tmp_path.join("main")), "key=a!");
+
+        let err = render(&project, &Filter::default(), true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse template: broken1.tera:1:12"
+        );
+
+        // Changing the partial re-renders the templates including it
+        fs::remove_file(tmp_path.join("broken1.tera")).unwrap();
+        fs::remove_file(tmp_path.join("broken2.tera")).unwrap();
+        create_template(&tmp_path.join("partial.tera"), "key={{ key }}?");
+        render(&project, &Filter::default(), false).unwrap();
+        assert_eq!(read_file_contents(&tmp_path.join("main")), "key=a?!");
+    }
+
+    #[test]
+    fn test_missing_parent_only_fails_its_template() {
+        let tmp_dir = create_temp_dir();
+        let tmp_path = tmp_dir.path();
+
+        let config_path = tmp_path.join("config.toml");
+        let answer_path = tmp_path.join("answers.toml");
+        setup_config(&config_path, &answer_path, "tpl", vec![]);
+        setup_answers(&answer_path, r#"key = "a""#);
+        create_template(&tmp_path.join("bad.tpl"), r#"{% extends "nope.tpl" %}"#);
+        create_template(&tmp_path.join("good.tpl"), "key={{ key }}");
+
+        let project = load_project(tmp_path, &config_path);
+        let err = render(&project, &Filter::default(), false).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("Failed to compile template: bad.tpl"),
+            "{}",
+            message
+        );
+        assert!(message.contains("nope.tpl"), "{}", message);
+        assert_eq!(read_file_contents(&tmp_path.join("good")), "key=a");
+        assert!(!tmp_path.join("bad").exists());
+    }
+
     #[test]
     fn test_error_handling() {
         let tmp_dir = create_temp_dir();
@@ -143,30 +513,21 @@ mod tests {
 
         // Test missing answer file
         setup_config(&config_path, &answer_path, "tera", vec![]);
-        let global_args = GlobalArgs {
-            root: tmp_path.to_path_buf(),
-            config_path: config_path.clone(),
-        };
-        let result = render(&global_args);
+        let project = load_project(tmp_path, &config_path);
+        let result = render(&project, &Filter::default(), false);
         assert!(result.is_err(), "Should error on missing answer file");
 
         // Test invalid TOML
         setup_answers(&answer_path, "invalid = toml here");
-        let global_args = GlobalArgs {
-            root: tmp_path.to_path_buf(),
-            config_path: config_path.clone(),
-        };
-        let result = render(&global_args);
+        let project = load_project(tmp_path, &config_path);
+        let result = render(&project, &Filter::default(), false);
         assert!(result.is_err(), "Should error on invalid TOML");
 
         // Test invalid template syntax
         setup_answers(&answer_path, r#"key = "value""#);
         create_template(&invalid_template_path, "{{ invalid syntax }}");
-        let global_args = GlobalArgs {
-            root: tmp_path.to_path_buf(),
-            config_path,
-        };
-        let result = render(&global_args);
+        let project = load_project(tmp_path, &config_path);
+        let result = render(&project, &Filter::default(), false);
         assert!(result.is_err(), "Should error on invalid template syntax");
     }
 }
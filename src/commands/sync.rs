@@ -1,24 +1,140 @@
-use anyhow::Result;
+use std::path::{Path, PathBuf};
 
-use crate::args::{AskArgs, GlobalArgs, SyncArgs};
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSetBuilder};
+
+use crate::args::{AskArgs, Phase, SyncArgs};
 use crate::commands::ask::ask;
-use crate::commands::install::install;
+use crate::commands::install::run_tasks;
 use crate::commands::link::link;
 use crate::commands::render::render;
+use crate::config::{ChangeHook, Task};
+use crate::filter::Filter;
+use crate::project::Project;
+
+pub fn sync(args: SyncArgs, project: &Project) -> Result<()> {
+    let cfg = &project.config;
+    let filter = Filter::new(&args.paths, &args.tags, &project.root, cfg)?;
+    let enabled = |phase: Phase| {
+        (args.only.is_empty() || args.only.contains(&phase)) && !args.skip.contains(&phase)
+    };
+    let run_hook = |name: &str, tasks: &[Task]| -> Result<()> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+        run_tasks(tasks, project, &Filter::default())
+            .with_context(|| format!("Failed to run {} hook", name))
+    };
+
+    run_hook("pre_sync", &cfg.hooks.pre_sync)?;
 
-pub fn sync(args: SyncArgs, global: &GlobalArgs) -> Result<()> {
     // Ask
-    let args = AskArgs { force: args.ask };
-    ask(args, global)?;
+    if enabled(Phase::Ask) {
+        let ask_args = AskArgs {
+            force: args.ask,
+            answers: args.answers.clone(),
+        };
+        ask(ask_args, project)?;
+    }
 
-    // Link link
-    link(global)?;
+    let mut changed = Vec::new();
+
+    // Link files
+    if enabled(Phase::Link) {
+        changed.extend(link(project, &filter, project.settings.fail_fast)?);
+        run_hook("post_link", &cfg.hooks.post_link)?;
+    }
 
     // Render templates
-    render(global)?;
+    if enabled(Phase::Render) {
+        changed.extend(render(project, &filter, project.settings.fail_fast)?);
+        run_hook("post_render", &cfg.hooks.post_render)?;
+    }
 
     // Install
-    install(global)?;
+    if enabled(Phase::Install) {
+        run_tasks(&cfg.tasks, project, &filter)?;
+    }
+
+    // Hooks for changed files
+    for hook in &cfg.hooks.on_change {
+        if hook_matches(hook, &changed, &project.root)? {
+            run_hook("on_change", &hook.tasks)?;
+        }
+    }
+
+    run_hook("post_sync", &cfg.hooks.post_sync)?;
 
     Ok(())
 }
+
+/// Whether any of the changed files matches the globs of the hook.
+fn hook_matches(hook: &ChangeHook, changed: &[PathBuf], root: &Path) -> Result<bool> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in &hook.paths {
+        builder.add(Glob::new(pattern).with_context(|| format!("Invalid glob: {}", pattern))?);
+    }
+    let globs = builder.build()?;
+
+    Ok(changed.iter().any(|path| {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        globs.is_match(relative) || globs.is_match(path)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::args::{AnswerArgs, GlobalArgs, InstallArgs};
+
+    #[test]
+    fn test_hook_matches() {
+        let root = Path::new("/dotfiles");
+        let hook = ChangeHook {
+            paths: vec!["tmux/*.conf".into()],
+            tasks: vec![],
+        };
+
+        let matching = [PathBuf::from("/dotfiles/tmux/tmux.conf")];
+        let other = [PathBuf::from("/dotfiles/nvim/init.lua")];
+        assert!(hook_matches(&hook, &matching, root).unwrap());
+        assert!(!hook_matches(&hook, &other, root).unwrap());
+        assert!(!hook_matches(&hook, &[], root).unwrap());
+    }
+
+    #[test]
+    fn test_sync_runs_pre_sync_hook_before_first_ask() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("sams.toml"),
+            "[[hooks.pre_sync]]\nrun = \"echo {{ os }} > pre\"",
+        )
+        .unwrap();
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+
+        let args = SyncArgs {
+            ask: false,
+            paths: Vec::new(),
+            only: Vec::new(),
+            skip: Vec::new(),
+            tags: Vec::new(),
+            fail_fast: false,
+            answers: AnswerArgs::default(),
+            install: InstallArgs::default(),
+        };
+        sync(args, &project).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("pre")).unwrap().trim(),
+            std::env::consts::OS
+        );
+        assert!(project.answer_file().exists());
+    }
+}
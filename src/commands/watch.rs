@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use notify::{Event, RecursiveMode, Watcher};
+
+use crate::args::{GlobalArgs, WatchArgs};
+use crate::commands::link::link;
+use crate::commands::render::render;
+use crate::config::Config;
+use crate::filter::Filter;
+use crate::project::Project;
+use crate::settings::{Layer, Settings};
+use crate::walk::WalkOptions;
+
+/// What has to be redone for a batch of changed files
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    /// The config or the answers changed, so everything is rendered and linked again
+    reload: bool,
+
+    /// Changed templates, relative to the root
+    templates: Vec<PathBuf>,
+
+    /// Changed link sources, relative to the root
+    links: Vec<PathBuf>,
+}
+
+pub fn watch(args: WatchArgs, global: &GlobalArgs) -> Result<()> {
+    // Keep watching the same root, even if the config is broken for a while
+    let (root, config_path) = Project::locate(global)?;
+    let global = GlobalArgs {
+        root: Some(root.clone()),
+        config_path: global.config_path.clone(),
+    };
+    let config_path = resolve(&config_path);
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve root '{}'", root.display()))?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Failed to create file watcher")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch '{}'", root.display()))?;
+
+    let flags = Layer {
+        debounce: args.debounce,
+        ..Layer::default()
+    };
+    let mut project = reload(&global, &flags);
+    println!(
+        "{} {} for changes",
+        "👀  Watching".bold().blue(),
+        root.display()
+    );
+
+    let debounce = match &project {
+        Some(project) => project.settings.debounce,
+        None => args.debounce.unwrap_or(Settings::default().debounce),
+    };
+    let debounce = Duration::from_millis(debounce);
+    while let Some(paths) = next_batch(&rx, debounce) {
+        let changes = classify(
+            &paths,
+            &root,
+            &triggers(&config_path, project.as_ref()),
+            project.as_ref().map(|project| &project.config),
+        );
+
+        if changes.reload {
+            project = reload(&global, &flags);
+            continue;
+        }
+        let Some(project) = &project else {
+            continue;
+        };
+        // Templates can include each other, unchanged ones are skipped by the render anyway
+        if !changes.templates.is_empty() {
+            if let Err(err) = render(project, &Filter::default(), false) {
+                report(&err);
+            }
+        }
+        if !changes.links.is_empty() {
+            let links: Vec<PathBuf> = changes.links.iter().map(|path| root.join(path)).collect();
+            let result = Filter::new(&links, &[], &root, &project.config)
+                .and_then(|filter| link(project, &filter, false));
+            if let Err(err) = result {
+                report(&err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait for the next change and collect all changes until none happened for `debounce`.
+///
+/// Events for merely reading files are dropped, otherwise rendering would trigger itself.
+fn next_batch(rx: &Receiver<notify::Result<Event>>, debounce: Duration) -> Option<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut first = true;
+    loop {
+        let result = if first {
+            rx.recv().ok()?
+        } else {
+            match rx.recv_timeout(debounce) {
+                Ok(result) => result,
+                Err(_) => return Some(paths),
+            }
+        };
+        match result {
+            Ok(event) if !event.kind.is_access() => {
+                paths.extend(event.paths);
+                first = false;
+            },
+            Ok(_) => {},
+            Err(err) => report(&anyhow::Error::from(err).context("Failed to watch files")),
+        }
+    }
+}
+
+/// Load the project and render and link everything, returning `None` if the config is invalid.
+fn reload(global: &GlobalArgs, flags: &Layer) -> Option<Project> {
+    let project = match Project::load_with(global, flags.clone()) {
+        Ok(project) => project,
+        Err(err) => {
+            report(&err);
+            return None;
+        },
+    };
+    for result in [
+        render(&project, &Filter::default(), false),
+        link(&project, &Filter::default(), false),
+    ] {
+        if let Err(err) = result {
+            report(&err);
+        }
+    }
+    Some(project)
+}
+
+/// Files which trigger a full reload when they change, besides new config files in
+/// subdirectories.
+fn triggers(config_path: &Path, project: Option<&Project>) -> Vec<PathBuf> {
+    let mut files = vec![config_path.to_path_buf()];
+    if let Some(project) = project {
+        files.extend(project.files.iter().map(|file| resolve(file)));
+        files.push(resolve(&project.answer_file()));
+    }
+    files
+}
+
+/// Sort changed paths by what has to be done. The first trigger is the config file, a file
+/// with its name anywhere in the root is a config file of a subdirectory.
+fn classify(paths: &[PathBuf], root: &Path, triggers: &[PathBuf], cfg: Option<&Config>) -> Changes {
+    let mut changes = Changes::default();
+    for path in paths {
+        if triggers.contains(&resolve(path)) {
+            changes.reload = true;
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        if relative.starts_with(".git") {
+            continue;
+        }
+        if path.file_name().is_some() && path.file_name() == triggers[0].file_name() {
+            changes.reload = true;
+            continue;
+        }
+        let Some(cfg) = cfg else {
+            continue;
+        };
+        // Files the walk of render and link skips are not theirs to handle
+        let skipped = WalkOptions::from_config(cfg).skips(root, relative);
+        if !matches!(skipped, Ok(false)) {
+            continue;
+        }
+
+        let extension = relative.extension().and_then(|e| e.to_str());
+        let list = if extension == Some(cfg.template_suffix.as_str()) {
+            &mut changes.templates
+        } else if extension == Some(cfg.link_suffix.as_str()) {
+            &mut changes.links
+        } else {
+            continue;
+        };
+        if !list.iter().any(|p| p == relative) {
+            list.push(relative.to_path_buf());
+        }
+    }
+    changes
+}
+
+/// Resolve symlinks in the path if it exists, so it can be compared with watcher events.
+fn resolve(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn report(err: &anyhow::Error) {
+    eprintln!("{} {:#}", "✗   Error".bold().red(), err);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let root = Path::new("/dotfiles");
+        let cfg = Config {
+            exclude: vec!["private/".into()],
+            ..Config::default()
+        };
+        let triggers = [root.join("sams.toml")];
+        let paths: Vec<PathBuf> = [
+            "/dotfiles/nvim/init.lua.tpl",
+            "/dotfiles/nvim/init.lua.tpl",
+            "/dotfiles/nvim/init.lua",
+            "/dotfiles/zsh/zshrc.ln",
+            "/dotfiles/.git/index",
+            "/dotfiles/private/token.tpl",
+            "/dotfiles/.cache/zshrc.ln",
+            "/elsewhere/file.tpl",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let changes = classify(&paths, root, &triggers, Some(&cfg));
+        assert_eq!(
+            changes,
+            Changes {
+                reload: false,
+                templates: vec!["nvim/init.lua.tpl".into()],
+                links: vec!["zsh/zshrc.ln".into()],
+            }
+        );
+
+        let paths = [root.join("sams.toml"), root.join("zsh/zshrc.ln")];
+        assert_eq!(
+            classify(&paths[1..], root, &triggers, None),
+            Changes::default()
+        );
+        assert!(classify(&paths, root, &triggers, Some(&cfg)).reload);
+        let changes = classify(&paths, root, &triggers, None);
+        assert!(changes.reload);
+        assert!(changes.links.is_empty());
+
+        // A new config file in a subdirectory
+        let paths = [root.join("git/sams.toml")];
+        assert!(classify(&paths, root, &triggers, Some(&cfg)).reload);
+    }
+
+    #[test]
+    fn test_classify_skips_gitignored_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("nvim/build")).unwrap();
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+        fs::write(root.join("nvim/.gitignore"), "*.ln\n!keep.ln\n").unwrap();
+        let triggers = [root.join("sams.toml")];
+        let paths: Vec<PathBuf> = ["nvim/build/init.lua.tpl", "nvim/init.ln", "nvim/keep.ln"]
+            .iter()
+            .map(|path| root.join(path))
+            .collect();
+
+        let changes = classify(&paths, root, &triggers, Some(&Config::default()));
+        assert!(changes.templates.is_empty());
+        assert_eq!(changes.links, [PathBuf::from("nvim/keep.ln")]);
+    }
+}
@@ -1,22 +1,41 @@
+use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use toml;
 
-use crate::args::GlobalArgs;
+use crate::format::Format;
 
 /// The configuration of the application
 #[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// The JSON schema of the config, for editors of JSON config files
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+
+    /// The version of the config format, see `sams migrate-config`
+    #[serde(default = "default_version")]
+    pub version: u32,
+
     /// The file in which user parameter will be stored
     #[serde(default = "default_answer_file")]
     pub answer_file: PathBuf,
 
+    /// The file in which machine local state like already run tasks is stored
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+
+    /// The directory in which the output of install tasks is logged
+    #[serde(default = "default_log_dir")]
+    pub log_dir: PathBuf,
+
     /// The list of files to exclude when copying the template
     #[serde(default = "default_exclude")]
     pub exclude: Vec<String>,
@@ -33,6 +52,11 @@ pub struct Config {
     #[serde(default = "default_respect_gitignore")]
     pub respect_gitignore: bool,
 
+    /// Glob patterns of further config files, relative to this one, whose parameters, tasks,
+    /// hooks and tags are merged into this config
+    #[serde(default)]
+    pub include: Vec<String>,
+
     /// The list of parameters to ask the user
     #[serde(default = "default_parameters")]
     pub parameters: Vec<Parameter>,
@@ -40,26 +64,57 @@ pub struct Config {
     /// List of install task to run
     #[serde(default = "default_tasks")]
     pub tasks: Vec<Task>,
+
+    /// Tasks to run around the phases of a sync
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    /// Named groups of files, given as glob patterns relative to the root, to select with `--tag`
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            schema: None,
+            version: CONFIG_VERSION,
             answer_file: default_answer_file(),
+            state_file: default_state_file(),
+            log_dir: default_log_dir(),
             exclude: default_exclude(),
             template_suffix: default_template_suffix(),
             link_suffix: default_link_suffix(),
             respect_gitignore: default_respect_gitignore(),
+            include: vec![],
             parameters: default_parameters(),
             tasks: default_tasks(),
+            hooks: Hooks::default(),
+            tags: BTreeMap::new(),
         }
     }
 }
 
+/// The version of the config format this version of sams reads and writes
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Configs written before the format was versioned have the first version
+fn default_version() -> u32 {
+    1
+}
+
 fn default_answer_file() -> PathBuf {
     PathBuf::from(".sams-answers.toml")
 }
 
+fn default_state_file() -> PathBuf {
+    PathBuf::from(".sams-state.toml")
+}
+
+fn default_log_dir() -> PathBuf {
+    PathBuf::from(".sams-logs")
+}
+
 fn default_exclude() -> Vec<String> {
     Vec::new()
 }
@@ -84,8 +139,44 @@ fn default_tasks() -> Vec<Task> {
     Vec::new()
 }
 
+/// Tasks which are run at certain points of a sync
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    /// Tasks to run before anything else
+    #[serde(default)]
+    pub pre_sync: Vec<Task>,
+
+    /// Tasks to run after linking files
+    #[serde(default)]
+    pub post_link: Vec<Task>,
+
+    /// Tasks to run after rendering templates
+    #[serde(default)]
+    pub post_render: Vec<Task>,
+
+    /// Tasks to run once everything else is done
+    #[serde(default)]
+    pub post_sync: Vec<Task>,
+
+    /// Tasks to run when specific rendered or linked files changed
+    #[serde(default)]
+    pub on_change: Vec<ChangeHook>,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
-#[serde(tag = "kind", rename_all = "lowercase")]
+#[serde(deny_unknown_fields)]
+pub struct ChangeHook {
+    /// Glob patterns matched against the root relative paths of rendered outputs and
+    /// linked files (without suffix)
+    pub paths: Vec<String>,
+
+    /// Tasks to run if any matching file changed
+    pub tasks: Vec<Task>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
 pub enum Parameter {
     Select {
         /// The name of the parameter
@@ -108,6 +199,34 @@ pub enum Parameter {
         #[serde(default = "default_data_type", rename = "type")]
         data_type: DataType,
     },
+    List {
+        /// The name of the parameter
+        name: String,
+        /// The description which will be displayed in the prompt
+        description: Option<String>,
+        /// The fields which will be asked for every item of the list
+        fields: Vec<Parameter>,
+    },
+    Table {
+        /// The name of the parameter
+        name: String,
+        /// The description which will be displayed in the prompt
+        description: Option<String>,
+        /// The fields which make up the table
+        fields: Vec<Parameter>,
+    },
+}
+
+impl Parameter {
+    /// The name under which the answer is stored
+    pub fn name(&self) -> &str {
+        match self {
+            Parameter::Select { name, .. }
+            | Parameter::Text { name, .. }
+            | Parameter::List { name, .. }
+            | Parameter::Table { name, .. } => name,
+        }
+    }
 }
 
 fn default_data_type() -> DataType {
@@ -115,14 +234,39 @@ fn default_data_type() -> DataType {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
-#[serde(tag = "type", rename_all = "lowercase")]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
 pub struct Task {
     /// The script to run
-    pub script: PathBuf,
+    pub script: Option<PathBuf>,
+
+    /// An inline command to run instead of a script, rendered with the answers
+    pub run: Option<String>,
+
+    /// Arguments passed to the script or appended to the inline command
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Environment variables set for the command, values are rendered with the answers
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Kill the command if it runs longer than this many seconds
+    pub timeout: Option<u64>,
+
+    /// Render the script as a Tera template with the answers before running it
+    #[serde(default)]
+    pub template: bool,
 
     /// The name of the task
     pub name: Option<String>,
 
+    /// Identifier used to refer to the task in `depends_on`, defaults to the name or command
+    pub id: Option<String>,
+
+    /// Identifiers of tasks which have to finish before this task starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+
     /// The working directory in which the command will be executed
     #[serde(default = "default_workdir")]
     pub workdir: PathBuf,
@@ -130,6 +274,61 @@ pub struct Task {
     /// The shell to use to run the command
     #[serde(default = "default_shell")]
     pub shell: String,
+
+    /// A Tera expression which must evaluate to true for the task to run
+    pub when: Option<String>,
+
+    /// Skip the task if this path (relative to the working directory) exists
+    pub creates: Option<PathBuf>,
+
+    /// Skip the task if this command exits successfully
+    pub unless: Option<String>,
+
+    /// When the task should be run again
+    #[serde(default)]
+    pub run_policy: RunPolicy,
+
+    /// Tags to select the task with `--tag`
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RunPolicy {
+    /// Run the task on every install
+    #[default]
+    Always,
+    /// Run the task only once for each version of its command
+    Once,
+    /// Run the task whenever its command changed since the last run
+    OnChange,
+}
+
+impl Task {
+    /// The name shown in the progress output
+    pub fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.id.clone())
+            .unwrap_or_else(|| self.command_label())
+    }
+
+    /// The identifier used to refer to the task from other tasks and in the state file
+    pub fn key(&self) -> String {
+        self.id
+            .clone()
+            .or_else(|| self.name.clone())
+            .unwrap_or_else(|| self.command_label())
+    }
+
+    fn command_label(&self) -> String {
+        match (&self.script, &self.run) {
+            (Some(script), _) => script.display().to_string(),
+            (None, Some(run)) => run.clone(),
+            (None, None) => String::new(),
+        }
+    }
 }
 
 fn default_workdir() -> PathBuf {
@@ -140,7 +339,7 @@ fn default_shell() -> String {
     "sh".to_string()
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DataType {
     Int,
@@ -149,11 +348,7 @@ pub enum DataType {
 }
 
 impl Config {
-    pub fn from_args(global: &GlobalArgs) -> Result<Self> {
-        Self::from_file(&global.root.join(&global.config_path))
-    }
-
-    pub fn from_file(file_path: &PathBuf) -> Result<Self> {
+    pub fn from_file(file_path: &Path) -> Result<Self> {
         let mut file = File::open(file_path)
             .with_context(|| format!("Failed to open config file at '{}'", file_path.display()))?;
 
@@ -161,19 +356,86 @@ impl Config {
         file.read_to_string(&mut contents)
             .with_context(|| format!("Failed to read config file at '{}'", file_path.display()))?;
 
-        let config: Self = toml::from_str(&contents)
-            .with_context(|| format!("Failed to parse config file at '{}'", file_path.display()))?;
+        Self::parse(&contents, Format::from_path(file_path))
+            .with_context(|| format!("Failed to parse config file at '{}'", file_path.display()))
+    }
 
-        Ok(config)
+    /// Parse a config, rejecting unknown keys and versions other than [`CONFIG_VERSION`]
+    pub fn parse(contents: &str, format: Format) -> Result<Self> {
+        let version = supported_version(contents, format)?;
+        if version < CONFIG_VERSION {
+            bail!(
+                "The config has version {}, run `sams migrate-config` to update it to version {}",
+                version,
+                CONFIG_VERSION
+            );
+        }
+        deserialize(contents, format)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+/// The version of a config, without parsing the rest which may be in a different format
+pub fn config_version(contents: &str, format: Format) -> Result<u32> {
+    let config: serde_json::Value = format.parse(contents)?;
+    match config.get("version") {
+        None => Ok(default_version()),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .context("The config version must be a positive number"),
+    }
+}
+
+/// The version of a config, failing if it is newer than this version of sams supports
+pub fn supported_version(contents: &str, format: Format) -> Result<u32> {
+    let version = config_version(contents, format)?;
+    if version > CONFIG_VERSION {
+        bail!(
+            "The config has version {}, but this version of sams only supports version {}, \
+             please update sams",
+            version,
+            CONFIG_VERSION
+        );
+    }
+    Ok(version)
+}
+
+/// Deserialize a config file, suggesting the closest known key for a misspelled one
+pub fn deserialize<T: DeserializeOwned>(contents: &str, format: Format) -> Result<T> {
+    format.parse(contents).map_err(|err| {
+        let message = err.to_string();
+        match did_you_mean(&message) {
+            Some(key) => anyhow!("{}\nhelp: did you mean `{}`?", message.trim_end(), key),
+            None => err,
+        }
+    })
+}
+
+/// The known field closest to the unknown one of a serde error containing
+/// "unknown field `x`, expected one of `a`, `b`"
+fn did_you_mean(message: &str) -> Option<&str> {
+    let (field, expected) = message
+        .split_once("unknown field `")?
+        .1
+        .split_once("`, expected ")?;
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (strsim::jaro_winkler(field, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
 #[serde(untagged)]
 pub enum Value {
     Integer(i64),
     Float(f64),
     String(String),
+    Array(Vec<Value>),
+    Table(BTreeMap<String, Value>),
 }
 
 impl Display for Value {
@@ -182,6 +444,31 @@ impl Display for Value {
             Value::Integer(i) => write!(f, "{}", i),
             Value::Float(fl) => write!(f, "{}", fl),
             Value::String(s) => write!(f, "{}", s),
+            // Nested values are displayed as inline TOML
+            Value::Array(_) | Value::Table(_) => {
+                let value = toml::Value::try_from(self).map_err(|_| fmt::Error)?;
+                write!(f, "{}", value)
+            },
+        }
+    }
+}
+
+/// Booleans and dates, which parameters can not have, become strings
+impl From<toml::Value> for Value {
+    fn from(value: toml::Value) -> Self {
+        match value {
+            toml::Value::Integer(i) => Value::Integer(i),
+            toml::Value::Float(f) => Value::Float(f),
+            toml::Value::String(s) => Value::String(s),
+            toml::Value::Boolean(b) => Value::String(b.to_string()),
+            toml::Value::Datetime(d) => Value::String(d.to_string()),
+            toml::Value::Array(items) => Value::Array(items.into_iter().map(Value::from).collect()),
+            toml::Value::Table(table) => Value::Table(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
         }
     }
 }
@@ -195,11 +482,16 @@ mod tests {
     #[test]
     fn test_serialize_deserialize_config_with_select_parameter() {
         let config = Config {
+            schema: None,
+            version: 1,
             answer_file: PathBuf::from(".answers.toml"),
+            state_file: PathBuf::from(".state.toml"),
+            log_dir: PathBuf::from(".logs"),
             exclude: vec![],
             template_suffix: "tpl".to_string(),
             link_suffix: "ln".to_string(),
             respect_gitignore: true,
+            include: vec![],
             parameters: vec![
                 Parameter::Select {
                     name: "age".to_string(),
@@ -215,15 +507,21 @@ mod tests {
                 },
             ],
             tasks: vec![],
+            hooks: Hooks::default(),
+            tags: BTreeMap::new(),
         };
 
         // Expected readable YAML
         let expected_toml = r#"
+version = 1
 answer_file = ".answers.toml"
+state_file = ".state.toml"
+log_dir = ".logs"
 exclude = []
 template_suffix = "tpl"
 link_suffix = "ln"
 respect_gitignore = true
+include = []
 tasks = []
 
 [[parameters]]
@@ -237,6 +535,15 @@ kind = "text"
 name = "name"
 description = "Enter your name"
 type = "str"
+
+[hooks]
+pre_sync = []
+post_link = []
+post_render = []
+post_sync = []
+on_change = []
+
+[tags]
 "#
         .trim();
 
@@ -249,4 +556,68 @@ type = "str"
         println!("{:?}", deserialized_config.get("answer_file").unwrap());
         // assert_eq!(config, deserialized_config);
     }
+
+    #[test]
+    fn test_unknown_keys_are_rejected_with_suggestion() {
+        let err = Config::parse("template_sufix = \"tpl\"", Format::Toml).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("unknown field `template_sufix`"),
+            "{}",
+            message
+        );
+        assert!(
+            message.ends_with("help: did you mean `template_suffix`?"),
+            "{}",
+            message
+        );
+
+        let err = Config::parse(
+            "[[tasks]]\nrun = \"true\"\nworkdri = \"nvim\"",
+            Format::Toml,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().ends_with("help: did you mean `workdir`?"),
+            "{}",
+            err
+        );
+
+        let err = Config::parse("{\"template_sufix\": \"tpl\"}", Format::Json).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("help: did you mean `template_suffix`?"),
+            "{}",
+            err
+        );
+
+        let err = Config::parse("completely_unrelated = 1", Format::Toml).unwrap_err();
+        assert!(!err.to_string().contains("did you mean"), "{}", err);
+    }
+
+    #[test]
+    fn test_yaml_and_json_config() {
+        let yaml = "answer_file: answers.yaml\nparameters:\n  - kind: text\n    name: user\n";
+        let config = Config::parse(yaml, Format::Yaml).unwrap();
+        assert_eq!(config.answer_file, PathBuf::from("answers.yaml"));
+        assert_eq!(config.parameters[0].name(), "user");
+
+        let json = r#"{"$schema": "sams.schema.json", "tasks": [{"run": "true"}]}"#;
+        let config = Config::parse(json, Format::Json).unwrap();
+        assert_eq!(config.schema.as_deref(), Some("sams.schema.json"));
+        assert_eq!(config.tasks[0].key(), "true");
+    }
+
+    #[test]
+    fn test_config_version() {
+        assert_eq!(
+            Config::parse("", Format::Toml).unwrap().version,
+            CONFIG_VERSION
+        );
+        assert_eq!(config_version("version = 3", Format::Toml).unwrap(), 3);
+
+        let err = Config::parse("version = 99", Format::Toml).unwrap_err();
+        assert!(err.to_string().contains("please update sams"), "{}", err);
+        assert!(config_version("version = \"1\"", Format::Toml).is_err());
+    }
 }
@@ -1,19 +1,46 @@
-use std::fs;
 use std::path::Path;
+use std::process::Command;
+use std::{env, fs};
 
 use anyhow::{Context, Result};
 use tera;
 use toml::Table;
 
+use crate::format::Format;
+
 pub fn read_context<P: AsRef<Path>>(answer_file: P) -> Result<tera::Context> {
     let answers = read_answers(answer_file)?;
-    tera::Context::from_serialize(answers).context("Failed to serialize answers")
+    let mut ctx = builtin_context();
+    ctx.extend(tera::Context::from_serialize(answers).context("Failed to serialize answers")?);
+    Ok(ctx)
+}
+
+/// Variables describing the current machine, answers with the same name take precedence
+pub fn builtin_context() -> tera::Context {
+    let mut ctx = tera::Context::new();
+    ctx.insert("os", env::consts::OS);
+    ctx.insert("arch", env::consts::ARCH);
+    ctx.insert("hostname", &hostname());
+    ctx
+}
+
+fn hostname() -> String {
+    Command::new("hostname")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|name| name.trim().to_string())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .unwrap_or_default()
 }
 
-fn read_answers<P: AsRef<Path>>(answer_file: P) -> Result<Table> {
+/// Read an answer file in the format given by its extension
+pub fn read_answers<P: AsRef<Path>>(answer_file: P) -> Result<Table> {
     let path = answer_file.as_ref();
     let content = fs::read_to_string(path)
         .with_context(|| format!("Failed to read answer file: {}", path.display()))?;
-    toml::from_str(&content)
-        .with_context(|| format!("Failed to parse TOML from {}", path.display()))
+    Format::from_path(path)
+        .parse(&content)
+        .with_context(|| format!("Failed to parse answer file: {}", path.display()))
 }
@@ -0,0 +1,18 @@
+use anyhow::{anyhow, Error, Result};
+
+/// Combine the errors of processing several files into one error listing all of them.
+pub fn combine(errors: Vec<Error>, action: &str) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.into_iter().next().expect("One error")),
+        n => {
+            let messages: Vec<String> = errors.iter().map(|err| format!("{:#}", err)).collect();
+            Err(anyhow!(
+                "Failed to {} {} files:\n\n{}",
+                action,
+                n,
+                messages.join("\n\n")
+            ))
+        },
+    }
+}
@@ -0,0 +1,205 @@
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::config::{Config, Task};
+
+/// Restricts a sync to the files and tasks selected by paths and tags
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Root relative path prefixes, `None` if no paths were given
+    paths: Option<GlobSet>,
+
+    /// Files selected by the requested tags, `None` if no tags were given
+    tagged: Option<GlobSet>,
+
+    /// The requested tags
+    tags: Vec<String>,
+}
+
+impl Filter {
+    /// Relative paths are resolved against the current directory, like any other path given
+    /// on the command line
+    pub fn new(paths: &[PathBuf], tags: &[String], root: &Path, cfg: &Config) -> Result<Self> {
+        let paths = if paths.is_empty() {
+            None
+        } else {
+            let cwd = env::current_dir().context("Failed to get the current directory")?;
+            let canonical = root
+                .canonicalize()
+                .with_context(|| format!("Failed to resolve root '{}'", root.display()))?;
+            let root = absolute(root, &cwd);
+            let mut builder = GlobSetBuilder::new();
+            for path in paths {
+                let absolute = absolute(path, &cwd);
+                let path = absolute
+                    .strip_prefix(&canonical)
+                    .or_else(|_| absolute.strip_prefix(&root))
+                    .with_context(|| format!("Path '{}' is outside of the root", path.display()))?;
+                let path = path.to_str().context("Path is not valid UTF-8")?;
+                let prefix = path.trim_end_matches('/');
+                if prefix.is_empty() {
+                    builder.add(Glob::new("**")?);
+                    continue;
+                }
+                builder.add(Glob::new(prefix)?);
+                builder.add(Glob::new(&format!("{}/**", prefix))?);
+            }
+            Some(builder.build()?)
+        };
+
+        let tagged = if tags.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for tag in tags {
+                let known =
+                    cfg.tags.contains_key(tag) || cfg.tasks.iter().any(|t| t.tags.contains(tag));
+                if !known {
+                    bail!("Unknown tag: {}", tag);
+                }
+                for glob in cfg.tags.get(tag).into_iter().flatten() {
+                    builder
+                        .add(Glob::new(glob).with_context(|| format!("Invalid glob: {}", glob))?);
+                }
+            }
+            Some(builder.build()?)
+        };
+
+        Ok(Self {
+            paths,
+            tagged,
+            tags: tags.to_vec(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_none() && self.tagged.is_none()
+    }
+
+    /// Whether a file, given relative to the root, is selected
+    pub fn matches_file(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let path = normalize(path);
+        self.paths
+            .as_ref()
+            .is_some_and(|globs| globs.is_match(&path))
+            || self
+                .tagged
+                .as_ref()
+                .is_some_and(|globs| globs.is_match(&path))
+    }
+
+    /// Whether a task is selected by one of its tags or because its script is selected
+    pub fn matches_task(&self, task: &Task) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        if task.tags.iter().any(|tag| self.tags.contains(tag)) {
+            return true;
+        }
+        let path = match &task.script {
+            Some(script) => task.workdir.join(script),
+            None => task.workdir.clone(),
+        };
+        self.paths
+            .as_ref()
+            .is_some_and(|globs| globs.is_match(normalize(&path)))
+    }
+}
+
+/// Drop `.` components so `./nvim/init.lua` and `nvim/init.lua` compare equal.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// The absolute form of a path given on the command line, with `.` and `..` resolved
+/// lexically as the path may not exist
+fn absolute(path: &Path, cwd: &Path) -> PathBuf {
+    let mut absolute = PathBuf::new();
+    for component in cwd.join(path).components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                absolute.pop();
+            },
+            component => absolute.push(component),
+        }
+    }
+    absolute
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            tags: BTreeMap::from([("shell".into(), vec!["zsh/**".into(), "bashrc.tpl".into()])]),
+            tasks: vec![toml::from_str("script = \"fonts.sh\"\ntags = [\"fonts\"]").unwrap()],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let filter = Filter::new(&["nvim/".into()], &[], Path::new("."), &config()).unwrap();
+
+        assert!(filter.matches_file(Path::new("./nvim/init.lua.tpl")));
+        assert!(filter.matches_file(Path::new("nvim/lua/plugins.lua.tpl")));
+        assert!(!filter.matches_file(Path::new("nvim.tpl")));
+        assert!(!filter.matches_file(Path::new("zsh/zshrc.tpl")));
+
+        let task: Task = toml::from_str("script = \"install.sh\"\nworkdir = \"nvim\"").unwrap();
+        assert!(filter.matches_task(&task));
+        assert!(!filter.matches_task(&config().tasks[0]));
+    }
+
+    #[test]
+    fn test_paths_are_relative_to_the_current_directory() {
+        let cwd = env::current_dir().unwrap();
+        let root = cwd.join("src");
+        let filter = Filter::new(&["src/commands".into()], &[], &root, &config()).unwrap();
+        assert!(filter.matches_file(Path::new("commands/sync.rs")));
+        assert!(!filter.matches_file(Path::new("src/commands/sync.rs")));
+
+        let filter = Filter::new(&["./src/../src/".into()], &[], &root, &config()).unwrap();
+        assert!(filter.matches_file(Path::new("filter.rs")));
+
+        assert!(Filter::new(&["tests".into()], &[], &root, &config()).is_err());
+        assert_eq!(
+            absolute(Path::new("../b/./c"), Path::new("/a")),
+            Path::new("/b/c")
+        );
+    }
+
+    #[test]
+    fn test_tag_filter() {
+        let cfg = config();
+        let filter = Filter::new(&[], &["shell".into()], Path::new("."), &cfg).unwrap();
+        assert!(filter.matches_file(Path::new("zsh/zshrc.tpl")));
+        assert!(filter.matches_file(Path::new("bashrc.tpl")));
+        assert!(!filter.matches_file(Path::new("nvim/init.lua.tpl")));
+        assert!(!filter.matches_task(&cfg.tasks[0]));
+
+        let filter = Filter::new(&[], &["fonts".into()], Path::new("."), &cfg).unwrap();
+        assert!(filter.matches_task(&cfg.tasks[0]));
+
+        assert!(Filter::new(&[], &["missing".into()], Path::new("."), &cfg).is_err());
+    }
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        let filter = Filter::default();
+        assert!(filter.matches_file(Path::new("anything")));
+        assert!(filter.matches_task(&config().tasks[0]));
+    }
+}
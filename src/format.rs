@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The formats config and answer files can be written in, chosen by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// The format of a file by its extension, TOML unless it is `.yaml`, `.yml` or `.json`
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, contents: &str) -> Result<T> {
+        Ok(match self {
+            Format::Toml => toml::from_str(contents)?,
+            Format::Yaml => serde_yaml::from_str(contents)?,
+            Format::Json => serde_json::from_str(contents)?,
+        })
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        match self {
+            Format::Toml => toml::to_string(value).context("Failed to serialize to TOML"),
+            Format::Yaml => serde_yaml::to_string(value).context("Failed to serialize to YAML"),
+            Format::Json => serde_json::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .context("Failed to serialize to JSON"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml::Table;
+
+    use super::*;
+
+    #[test]
+    fn test_formats_round_trip() {
+        let table: Table = toml::from_str("name = \"me\"\nport = 22\n[git]\nsign = true").unwrap();
+
+        for (file, format) in [
+            ("answers.toml", Format::Toml),
+            ("answers.yaml", Format::Yaml),
+            ("answers.yml", Format::Yaml),
+            (".sams-answers.json", Format::Json),
+            ("answers", Format::Toml),
+        ] {
+            assert_eq!(Format::from_path(Path::new(file)), format);
+            let serialized = format.serialize(&table).unwrap();
+            assert_eq!(format.parse::<Table>(&serialized).unwrap(), table);
+        }
+    }
+}
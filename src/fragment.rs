@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::{fs, mem};
+
+use anyhow::{bail, Context, Result};
+use globset::GlobBuilder;
+use serde::Deserialize;
+
+use crate::config::{deserialize, Config, Hooks, Parameter, Task};
+use crate::format::Format;
+use crate::walk::WalkOptions;
+
+/// Part of the configuration, from an included file or the config file of a subdirectory
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(rename = "$schema", default)]
+    #[allow(dead_code)]
+    schema: Option<String>,
+
+    #[serde(default)]
+    include: Vec<String>,
+
+    #[serde(default)]
+    parameters: Vec<Parameter>,
+
+    #[serde(default)]
+    tasks: Vec<Task>,
+
+    #[serde(default)]
+    hooks: Hooks,
+
+    #[serde(default)]
+    tags: BTreeMap<String, Vec<String>>,
+}
+
+impl Fragment {
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file at '{}'", path.display()))?;
+        deserialize(&contents, Format::from_path(path))
+            .with_context(|| format!("Failed to parse config file at '{}'", path.display()))
+    }
+
+    /// Make paths relative to the root instead of the root relative directory `dir`
+    fn relocate(&mut self, dir: &Path) {
+        if dir.as_os_str().is_empty() {
+            return;
+        }
+        let prefix = |glob: &mut String| *glob = format!("{}/{}", dir.display(), glob);
+
+        let hooks = &mut self.hooks;
+        for task in self
+            .tasks
+            .iter_mut()
+            .chain(&mut hooks.pre_sync)
+            .chain(&mut hooks.post_link)
+            .chain(&mut hooks.post_render)
+            .chain(&mut hooks.post_sync)
+            .chain(hooks.on_change.iter_mut().flat_map(|hook| &mut hook.tasks))
+        {
+            task.workdir = dir.join(&task.workdir);
+        }
+        for hook in &mut hooks.on_change {
+            hook.paths.iter_mut().for_each(prefix);
+        }
+        for globs in self.tags.values_mut() {
+            globs.iter_mut().for_each(prefix);
+        }
+    }
+}
+
+/// Load the config file and merge the files it includes and the config files of
+/// subdirectories of the root into it.
+///
+/// Settings like `answer_file` can only be given in the main config file. Parameters, tasks,
+/// hooks and tags are appended in this order: the main config file, the files it includes,
+/// then the config files of subdirectories ordered by path, each followed by the files
+/// they include. Paths in the config file of a subdirectory are relative to it.
+///
+/// Returns the config and all files it was read from.
+pub fn load_config(config_path: &Path, root: &Path) -> Result<(Config, Vec<PathBuf>)> {
+    let mut config = Config::from_file(config_path)?;
+    let mut merger = Merger {
+        walk: IncludeWalk {
+            excluded: [config_path, &config.answer_file, &config.state_file]
+                .iter()
+                .map(|path| canonical(&root.join(path)))
+                .collect(),
+            respect_gitignore: config.respect_gitignore,
+        },
+        ..Merger::default()
+    };
+    merger.add(config_path, &config.parameters, &config.tasks)?;
+
+    let include = config.include.clone();
+    for path in merger.walk.resolve(config_path, &include)? {
+        merger.load(&mut config, &path, Path::new(""))?;
+    }
+
+    let Some(name) = config_path.file_name() else {
+        return Ok((config, merger.files));
+    };
+    let mut fragments = Vec::new();
+    for entry in WalkOptions::from_config(&config)
+        .walk(root)
+        .context("Failed to walk directory")?
+    {
+        let path = entry.context("Failed to read directory entry")?.into_path();
+        let dir = path.strip_prefix(root).unwrap_or(&path).parent();
+        match dir {
+            Some(dir) if !dir.as_os_str().is_empty() && path.file_name() == Some(name) => {
+                fragments.push((path.clone(), dir.to_path_buf()))
+            },
+            _ => {},
+        }
+    }
+    fragments.sort();
+    for (path, dir) in fragments {
+        merger.load(&mut config, &path, &dir)?;
+    }
+
+    Ok((config, merger.files))
+}
+
+/// Keeps track of where parameters and tasks were defined to report conflicts
+#[derive(Default)]
+struct Merger {
+    files: Vec<PathBuf>,
+    parameters: HashMap<String, PathBuf>,
+    tasks: HashMap<String, PathBuf>,
+    walk: IncludeWalk,
+}
+
+impl Merger {
+    fn add(&mut self, path: &Path, parameters: &[Parameter], tasks: &[Task]) -> Result<()> {
+        for param in parameters {
+            let name = param.name().to_string();
+            if let Some(other) = self.parameters.insert(name.clone(), path.to_path_buf()) {
+                if other != path {
+                    bail!(
+                        "Parameter '{}' is defined in both '{}' and '{}'",
+                        name,
+                        other.display(),
+                        path.display()
+                    );
+                }
+            }
+        }
+        // Tasks without an id only have to be told apart when they are depended on, which
+        // the task graph checks
+        for id in tasks.iter().filter_map(|task| task.id.as_ref()) {
+            if let Some(other) = self.tasks.insert(id.clone(), path.to_path_buf()) {
+                if other != path {
+                    bail!(
+                        "Task id '{}' is used in both '{}' and '{}'",
+                        id,
+                        other.display(),
+                        path.display()
+                    );
+                }
+            }
+        }
+        self.files.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Merge a config file and the files it includes, relocating them to `dir`
+    fn load(&mut self, config: &mut Config, path: &Path, dir: &Path) -> Result<()> {
+        if self.files.iter().any(|file| file == path) {
+            return Ok(());
+        }
+
+        let mut fragment = Fragment::from_file(path)?;
+        fragment.relocate(dir);
+        self.add(path, &fragment.parameters, &fragment.tasks)?;
+        let include = mem::take(&mut fragment.include);
+
+        config.parameters.extend(fragment.parameters);
+        config.tasks.extend(fragment.tasks);
+        let hooks = &mut config.hooks;
+        hooks.pre_sync.extend(fragment.hooks.pre_sync);
+        hooks.post_link.extend(fragment.hooks.post_link);
+        hooks.post_render.extend(fragment.hooks.post_render);
+        hooks.post_sync.extend(fragment.hooks.post_sync);
+        hooks.on_change.extend(fragment.hooks.on_change);
+        for (tag, globs) in fragment.tags {
+            config.tags.entry(tag).or_default().extend(globs);
+        }
+
+        for included in self.walk.resolve(path, &include)? {
+            self.load(config, &included, dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the files matched by include patterns
+#[derive(Default)]
+struct IncludeWalk {
+    /// Canonical paths of the config, answer and state file, which globs never match
+    excluded: Vec<PathBuf>,
+
+    respect_gitignore: bool,
+}
+
+impl IncludeWalk {
+    /// The files matched by the include patterns of a config file, relative to its directory.
+    ///
+    /// Globs skip hidden and ignored files like the walk for templates and links, and `*` does
+    /// not match `/`.
+    fn resolve(&self, path: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut files = Vec::new();
+        for pattern in patterns {
+            if !pattern.contains(['*', '?', '[', '{']) {
+                let file = dir.join(pattern);
+                if !file.is_file() {
+                    bail!(
+                        "File '{}' included from '{}' does not exist",
+                        file.display(),
+                        path.display()
+                    );
+                }
+                files.push(file);
+                continue;
+            }
+
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid include in '{}': {}", path.display(), pattern))?
+                .compile_matcher();
+            let walk = WalkOptions {
+                respect_gitignore: self.respect_gitignore,
+                ignore_hidden: true,
+                ..WalkOptions::default()
+            };
+            let mut matches = Vec::new();
+            for entry in walk.walk(dir).context("Failed to walk directory")? {
+                let file = entry.context("Failed to read directory entry")?.into_path();
+                let relative = file.strip_prefix(dir).unwrap_or(&file);
+                if glob.is_match(relative) && !self.excluded.contains(&canonical(&file)) {
+                    matches.push(file);
+                }
+            }
+            matches.sort();
+            files.extend(matches);
+        }
+        Ok(files)
+    }
+}
+
+/// The canonical form of an existing path, otherwise the path itself
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_load_config_merges_includes_and_fragments() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(
+            &root.join("sams.toml"),
+            "include = [\"tasks/*.toml\"]\n[[tasks]]\nid = \"main\"\nrun = \"true\"",
+        );
+        write(
+            &root.join("tasks/b.toml"),
+            "[[tasks]]\nid = \"b\"\nrun = \"true\"",
+        );
+        write(
+            &root.join("tasks/a.toml"),
+            "include = [\"../params.toml\"]\n[[tasks]]\nid = \"a\"\nrun = \"true\"",
+        );
+        write(
+            &root.join("params.toml"),
+            "[[parameters]]\nkind = \"text\"\nname = \"user\"",
+        );
+        write(
+            &root.join("nvim/sams.toml"),
+            "[[tasks]]\nid = \"nvim\"\nscript = \"install.sh\"\n[tags]\nnvim = [\"**\"]",
+        );
+
+        let (config, files) = load_config(&root.join("sams.toml"), root).unwrap();
+
+        let ids: Vec<String> = config.tasks.iter().map(Task::key).collect();
+        assert_eq!(ids, ["main", "a", "b", "nvim"]);
+        assert_eq!(config.parameters[0].name(), "user");
+        assert_eq!(config.tasks[3].workdir, PathBuf::from("nvim/."));
+        assert_eq!(config.tags["nvim"], ["nvim/**"]);
+        assert_eq!(files.len(), 5);
+    }
+
+    #[test]
+    fn test_load_config_errors_name_the_file() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(
+            &root.join("sams.toml"),
+            "[[parameters]]\nkind = \"text\"\nname = \"user\"",
+        );
+        write(
+            &root.join("zsh/sams.toml"),
+            "answer_file = \"answers.toml\"",
+        );
+
+        let err = format!(
+            "{:#}",
+            load_config(&root.join("sams.toml"), root).unwrap_err()
+        );
+        assert!(err.contains("zsh/sams.toml"), "{}", err);
+        assert!(err.contains("answer_file"), "{}", err);
+
+        write(
+            &root.join("zsh/sams.toml"),
+            "[[parameters]]\nkind = \"text\"\nname = \"user\"",
+        );
+        let err = load_config(&root.join("sams.toml"), root)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Parameter 'user' is defined in both"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_include_globs_skip_generated_and_nested_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        write(
+            &root.join("sams.toml"),
+            "answer_file = \"answers.toml\"\ninclude = [\"*.toml\"]",
+        );
+        write(&root.join("answers.toml"), "user = \"sams\"");
+        write(&root.join(".local.toml"), "[[tasks]]\nrun = \"true\"");
+        write(&root.join("nested/tasks.toml"), "[[tasks]]\nrun = \"true\"");
+        write(
+            &root.join("tasks.toml"),
+            "[[tasks]]\nscript = \"install.sh\"",
+        );
+        write(
+            &root.join("nvim/sams.toml"),
+            "[[tasks]]\nscript = \"install.sh\"",
+        );
+
+        let (config, files) = load_config(&root.join("sams.toml"), root).unwrap();
+        assert_eq!(
+            files,
+            [
+                root.join("sams.toml"),
+                root.join("tasks.toml"),
+                root.join("nvim/sams.toml")
+            ]
+        );
+        assert_eq!(config.tasks.len(), 2);
+
+        write(
+            &root.join("tasks.toml"),
+            "[[tasks]]\nid = \"install\"\nrun = \"true\"",
+        );
+        write(
+            &root.join("nvim/sams.toml"),
+            "[[tasks]]\nid = \"install\"\nrun = \"true\"",
+        );
+        let err = load_config(&root.join("sams.toml"), root)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("Task id 'install' is used in both"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::config::Config;
+use crate::project::Project;
+use crate::state::State;
+use crate::{atomic, git};
+
+/// Lines enclosing the part of `.gitignore` maintained by sams
+const BLOCK_START: &str = "# >>> sams: generated files, do not edit >>>";
+const BLOCK_END: &str = "# <<< sams <<<";
+
+/// The files sams writes into the root, which belong to the machine they were written on
+/// and not into the repository
+#[derive(Debug, Default, PartialEq)]
+pub struct Generated {
+    /// Root relative paths, directories stand for everything below them
+    paths: BTreeSet<PathBuf>,
+}
+
+impl Generated {
+    /// The answer file, the state file, the log directory and the outputs of all templates
+    /// rendered so far, including those of deleted templates which still exist
+    pub fn load(project: &Project) -> Result<Self> {
+        let state = State::load(&project.state_file())?;
+        Ok(Self::new(&project.config, &project.root, &state))
+    }
+
+    pub fn new(cfg: &Config, root: &Path, state: &State) -> Self {
+        let outputs = state
+            .renders
+            .keys()
+            .map(|name| Path::new(name).with_extension(""))
+            .chain(state.stale.iter().map(PathBuf::from));
+        let paths = [&cfg.answer_file, &cfg.state_file, &cfg.log_dir]
+            .into_iter()
+            .filter_map(|path| relative(path, root))
+            .chain(outputs)
+            .collect();
+        Self { paths }
+    }
+
+    /// Whether the root relative path was generated, or is below a generated directory
+    pub fn contains(&self, path: &Path) -> bool {
+        path.ancestors().any(|path| self.paths.contains(path))
+    }
+
+    /// The generated files git tracks below the root
+    pub fn tracked(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        let tracked = git::output(root, ["ls-files", "-z"])?;
+        Ok(tracked
+            .split('\0')
+            .map(PathBuf::from)
+            .filter(|path| !path.as_os_str().is_empty() && self.contains(path))
+            .collect())
+    }
+
+    /// Write the generated files into the block of `.gitignore` in the root maintained by
+    /// sams, keeping everything around it. Returns whether the file changed.
+    pub fn update_gitignore(&self, root: &Path) -> Result<bool> {
+        let path = root.join(".gitignore");
+        let existing = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read: {}", path.display()))?
+        } else {
+            String::new()
+        };
+
+        let updated = replace_block(&existing, &self.gitignore_block());
+        if updated == existing {
+            return Ok(false);
+        }
+        atomic::write(&path, updated)
+            .with_context(|| format!("Failed to write: {}", path.display()))?;
+        Ok(true)
+    }
+
+    /// The block of `.gitignore` maintained by sams, anchoring every path at the root
+    fn gitignore_block(&self) -> String {
+        let mut block = format!("{}\n", BLOCK_START);
+        for path in &self.paths {
+            let mut pattern = String::from("/");
+            for c in path.to_string_lossy().chars() {
+                if matches!(c, '\\' | '*' | '?' | '[' | '!' | '#') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            block.push_str(&pattern);
+            block.push('\n');
+        }
+        block.push_str(BLOCK_END);
+        block.push('\n');
+        block
+    }
+}
+
+/// Replace the block maintained by sams in `content`, appending it if there is none yet
+fn replace_block(content: &str, block: &str) -> String {
+    let start = content.find(BLOCK_START);
+    let end = content
+        .find(BLOCK_END)
+        .map(|end| end + BLOCK_END.len())
+        .map(|end| end + usize::from(content[end..].starts_with('\n')));
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{}{}", &content[..start], block, &content[end..])
+        },
+        _ if content.is_empty() => block.to_string(),
+        _ if content.ends_with('\n') => format!("{}\n{}", content, block),
+        _ => format!("{}\n\n{}", content, block),
+    }
+}
+
+/// Whether the directory is part of a git repository
+pub fn in_git_repo(dir: &Path) -> bool {
+    path::absolute(dir).is_ok_and(|dir| dir.ancestors().any(|dir| dir.join(".git").exists()))
+}
+
+/// A path from the config relative to the root, `None` if it lies outside of the root
+fn relative(path: &Path, root: &Path) -> Option<PathBuf> {
+    match path.strip_prefix(root) {
+        Ok(path) => Some(path.to_path_buf()),
+        Err(_) if path.is_relative() && !path.starts_with("..") => Some(path.to_path_buf()),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::args::GlobalArgs;
+    use crate::commands::render::render;
+    use crate::filter::Filter;
+    use crate::state::content_hash;
+
+    #[test]
+    fn test_generated_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("sams.toml"), "log_dir = \"/var/log/sams\"").unwrap();
+        let mut state = State::default();
+        state
+            .renders
+            .insert("git/gitconfig.tpl".into(), content_hash(b""));
+        state.save(&dir.path().join(".sams-state.toml")).unwrap();
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+
+        let generated = Generated::load(&project).unwrap();
+        assert!(generated.contains(Path::new(".sams-answers.toml")));
+        assert!(generated.contains(Path::new(".sams-state.toml")));
+        assert!(generated.contains(Path::new("git/gitconfig")));
+        assert!(!generated.contains(Path::new("git/gitconfig.tpl")));
+        assert!(!generated.contains(Path::new("git")));
+        assert_eq!(generated.paths.len(), 3);
+    }
+
+    #[test]
+    fn test_update_gitignore() {
+        let dir = tempdir().unwrap();
+        let mut state = State::default();
+        state
+            .renders
+            .insert("git/[work]config.tpl".into(), content_hash(b""));
+        let generated = Generated::new(&Config::default(), dir.path(), &state);
+
+        let gitignore = dir.path().join(".gitignore");
+        fs::write(&gitignore, "target/").unwrap();
+        assert!(generated.update_gitignore(dir.path()).unwrap());
+        let block = "# >>> sams: generated files, do not edit >>>\n/.sams-answers.toml\n\
+                     /.sams-logs\n/.sams-state.toml\n/git/\\[work]config\n# <<< sams <<<\n";
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            format!("target/\n\n{}", block)
+        );
+
+        fs::write(&gitignore, format!("target/\n{}*.swp\n", block)).unwrap();
+        assert!(!generated.update_gitignore(dir.path()).unwrap());
+
+        let generated = Generated::new(&Config::default(), dir.path(), &State::default());
+        assert!(generated.update_gitignore(dir.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore).unwrap(),
+            "target/\n# >>> sams: generated files, do not edit >>>\n/.sams-answers.toml\n\
+             /.sams-logs\n/.sams-state.toml\n# <<< sams <<<\n*.swp\n"
+        );
+    }
+
+    #[test]
+    fn test_outputs_of_deleted_templates_stay_generated() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("sams.toml"), "").unwrap();
+        fs::write(root.join(".sams-answers.toml"), "").unwrap();
+        fs::write(root.join("gitconfig.tpl"), "[user]").unwrap();
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(root.to_path_buf()),
+        })
+        .unwrap();
+        let output = Path::new("gitconfig");
+
+        render(&project, &Filter::default(), false).unwrap();
+        fs::remove_file(root.join("gitconfig.tpl")).unwrap();
+        render(&project, &Filter::default(), false).unwrap();
+        assert!(Generated::load(&project).unwrap().contains(output));
+
+        fs::remove_file(root.join(output)).unwrap();
+        render(&project, &Filter::default(), false).unwrap();
+        assert!(!Generated::load(&project).unwrap().contains(output));
+        assert_eq!(
+            State::load(&project.state_file()).unwrap(),
+            State::default()
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use anyhow::{bail, Context, Result};
+
+/// Run git in `dir`, failing if it does not exit successfully. Its output is shown to the
+/// user.
+pub fn run<I, S>(dir: &Path, args: I) -> Result<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let (name, mut cmd) = command(dir, args);
+    let status = cmd
+        .status()
+        .with_context(|| format!("Failed to execute `git {}`", name))?;
+    if !status.success() {
+        bail!("`git {}` failed with {}", name, status);
+    }
+    Ok(())
+}
+
+/// Run git in `dir` and return its standard output, failing with its error output if it
+/// does not exit successfully
+pub fn output<I, S>(dir: &Path, args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let (name, mut cmd) = command(dir, args);
+    let Output {
+        status,
+        stdout,
+        stderr,
+    } = cmd
+        .output()
+        .with_context(|| format!("Failed to execute `git {}`", name))?;
+    if !status.success() {
+        bail!(
+            "`git {}` failed with {}: {}",
+            name,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+    String::from_utf8(stdout).with_context(|| format!("`git {}` printed invalid UTF-8", name))
+}
+
+/// The git command and the name of its subcommand, skipping `-c key=value` options
+fn command<I, S>(dir: &Path, args: I) -> (String, Command)
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new("git");
+    cmd.args(args).current_dir(dir);
+    let mut args = cmd.get_args().map(|arg| arg.to_string_lossy());
+    let mut name = String::new();
+    while let Some(arg) = args.next() {
+        if arg == "-c" {
+            args.next();
+        } else {
+            name = arg.into_owned();
+            break;
+        }
+    }
+    (name, cmd)
+}
+
+/// Helpers for tests working with git repositories
+#[cfg(test)]
+pub mod test_utils {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::args::GlobalArgs;
+
+    /// Run git with a fixed identity and return its output
+    pub fn git(dir: &Path, args: &[&str]) -> String {
+        let identity = ["-c", "user.name=sams", "-c", "user.email=sams@example.com"];
+        super::output(dir, identity.iter().chain(args)).unwrap()
+    }
+
+    /// Write a file and commit it
+    pub fn commit(dir: &Path, file: &str, content: &str) {
+        fs::write(dir.join(file), content).unwrap();
+        git(dir, &["add", file]);
+        git(dir, &["commit", "--quiet", "-m", file]);
+    }
+
+    /// A new repository in `dir` on branch `main` with an empty committed `sams.toml`
+    pub fn init(dir: &Path) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        git(dir, &["init", "--quiet", "--initial-branch", "main"]);
+        commit(dir, "sams.toml", "");
+        dir.to_path_buf()
+    }
+
+    /// The arguments selecting the project with a `sams.toml` in `root`
+    pub fn global(root: &Path) -> GlobalArgs {
+        GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(root.to_path_buf()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_failing_command() {
+        let dir = tempdir().unwrap();
+        let err = output(dir.path(), ["-c", "core.pager=cat", "log"]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("`git log` failed with"),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains("not a git repository"), "{}", err);
+
+        run(dir.path(), ["init", "--quiet"]).unwrap();
+        assert_eq!(
+            output(dir.path(), ["rev-parse", "--is-inside-work-tree"]).unwrap(),
+            "true\n"
+        );
+    }
+}
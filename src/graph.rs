@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::config::Task;
+
+/// Dependency graph between install tasks
+#[derive(Debug)]
+pub struct TaskGraph {
+    /// Indices of the tasks each task depends on
+    deps: Vec<Vec<usize>>,
+}
+
+impl TaskGraph {
+    /// Build the graph, resolving `depends_on` references.
+    ///
+    /// References resolve to the task with that `id`, or else to the task whose name or
+    /// command is the reference. Only explicit ids have to be unique, a reference matching
+    /// the name or command of several tasks is an error.
+    pub fn new(tasks: &[Task]) -> Result<Self> {
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        let mut fallbacks: HashMap<String, Vec<usize>> = HashMap::new();
+        for (idx, task) in tasks.iter().enumerate() {
+            match &task.id {
+                Some(id) => {
+                    if ids.insert(id, idx).is_some() {
+                        bail!("Duplicate task id: {}", id);
+                    }
+                },
+                None => fallbacks.entry(task.key()).or_default().push(idx),
+            }
+        }
+
+        let mut deps = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let mut task_deps = Vec::with_capacity(task.depends_on.len());
+            for dep in &task.depends_on {
+                let idx = match (ids.get(dep.as_str()), fallbacks.get(dep).map(Vec::as_slice)) {
+                    (Some(&idx), _) => idx,
+                    (None, Some(&[idx])) => idx,
+                    (None, Some(matches)) => bail!(
+                        "Task '{}' depends on '{}', which matches {} tasks, give the one \
+                         meant an `id`",
+                        task.key(),
+                        dep,
+                        matches.len()
+                    ),
+                    (None, None) => {
+                        bail!("Task '{}' depends on unknown task '{}'", task.key(), dep)
+                    },
+                };
+                task_deps.push(idx);
+            }
+            deps.push(task_deps);
+        }
+
+        let graph = Self { deps };
+        if let Some(cycle) = graph.find_cycle() {
+            let names: Vec<String> = cycle.iter().map(|&idx| tasks[idx].key()).collect();
+            bail!("Dependency cycle between tasks: {}", names.join(" -> "));
+        }
+        Ok(graph)
+    }
+
+    /// The first task in declaration order which has not been started and whose
+    /// dependencies are all done.
+    pub fn next_ready(&self, started: &[bool], done: &[bool]) -> Option<usize> {
+        (0..self.deps.len())
+            .find(|&idx| !started[idx] && self.deps[idx].iter().all(|&dep| done[dep]))
+    }
+
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Active,
+            Finished,
+        }
+
+        fn visit(
+            graph: &TaskGraph,
+            idx: usize,
+            marks: &mut [Mark],
+            path: &mut Vec<usize>,
+        ) -> Option<Vec<usize>> {
+            match marks[idx] {
+                Mark::Finished => return None,
+                Mark::Active => {
+                    let start = path.iter().position(|&i| i == idx).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(idx);
+                    return Some(cycle);
+                },
+                Mark::New => {},
+            }
+
+            marks[idx] = Mark::Active;
+            path.push(idx);
+            for &dep in &graph.deps[idx] {
+                if let Some(cycle) = visit(graph, dep, marks, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            marks[idx] = Mark::Finished;
+            None
+        }
+
+        let mut marks = vec![Mark::New; self.deps.len()];
+        (0..self.deps.len()).find_map(|idx| visit(self, idx, &mut marks, &mut Vec::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, depends_on: &[&str]) -> Task {
+        toml::from_str(&format!(
+            "script = \"{id}.sh\"\nid = \"{id}\"\ndepends_on = {depends_on:?}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_next_ready_respects_dependencies() {
+        let tasks = vec![task("a", &["b"]), task("b", &[]), task("c", &[])];
+        let graph = TaskGraph::new(&tasks).unwrap();
+
+        let mut started = vec![false; 3];
+        let mut done = vec![false; 3];
+
+        assert_eq!(graph.next_ready(&started, &done), Some(1));
+        started[1] = true;
+        assert_eq!(graph.next_ready(&started, &done), Some(2));
+        started[2] = true;
+        assert_eq!(graph.next_ready(&started, &done), None);
+        done[1] = true;
+        assert_eq!(graph.next_ready(&started, &done), Some(0));
+    }
+
+    #[test]
+    fn test_invalid_graphs() {
+        let cycle = vec![task("a", &["c"]), task("b", &["a"]), task("c", &["b"])];
+        let err = TaskGraph::new(&cycle).unwrap_err().to_string();
+        assert_eq!(err, "Dependency cycle between tasks: a -> c -> b -> a");
+
+        let unknown = vec![task("a", &["missing"])];
+        assert!(TaskGraph::new(&unknown).is_err());
+
+        let duplicate = vec![task("a", &[]), task("a", &[])];
+        assert!(TaskGraph::new(&duplicate).is_err());
+    }
+
+    #[test]
+    fn test_fallback_keys_only_need_to_be_unique_when_referenced() {
+        let unnamed = |script: &str, depends_on: &[&str]| -> Task {
+            toml::from_str(&format!(
+                "script = \"{script}\"\ndepends_on = {depends_on:?}"
+            ))
+            .unwrap()
+        };
+
+        let same_script = vec![unnamed("install.sh", &[]), unnamed("install.sh", &[])];
+        assert!(TaskGraph::new(&same_script).is_ok());
+
+        let unique = vec![unnamed("fonts.sh", &[]), unnamed("nvim.sh", &["fonts.sh"])];
+        let graph = TaskGraph::new(&unique).unwrap();
+        assert_eq!(graph.deps[1], vec![0]);
+
+        let ambiguous = vec![
+            unnamed("install.sh", &[]),
+            unnamed("install.sh", &[]),
+            unnamed("nvim.sh", &["install.sh"]),
+        ];
+        let err = TaskGraph::new(&ambiguous).unwrap_err().to_string();
+        assert!(err.contains("which matches 2 tasks"), "{}", err);
+
+        let explicit = vec![
+            task("install.sh", &[]),
+            unnamed("install.sh", &[]),
+            unnamed("nvim.sh", &["install.sh"]),
+        ];
+        assert_eq!(TaskGraph::new(&explicit).unwrap().deps[2], vec![0]);
+    }
+}
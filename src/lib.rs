@@ -4,18 +4,34 @@ use anyhow::Result;
 use clap::CommandFactory;
 
 use crate::args::{Args, Commands};
+use crate::filter::Filter;
+use crate::project::Project;
+use crate::settings::Layer;
 
 pub mod args;
+pub mod atomic;
 pub mod commands;
 pub mod config;
 pub mod context;
+pub mod errors;
+pub mod filter;
+pub mod format;
+pub mod fragment;
+pub mod generated;
+pub mod git;
+pub mod graph;
+pub mod parallel;
+pub mod project;
+pub mod settings;
+pub mod state;
 pub mod template;
 pub mod walk;
 
 pub fn run(Args { global, command }: Args) -> Result<ExitCode> {
     match command {
         Commands::Sync(args) => {
-            commands::sync::sync(args, &global)?;
+            let flags = Layer::from_install_args(&args.install).with_fail_fast(args.fail_fast);
+            commands::sync::sync(args, &Project::load_with(&global, flags)?)?;
             Ok(ExitCode::SUCCESS)
         },
         Commands::Init(args) => {
@@ -27,27 +43,58 @@ pub fn run(Args { global, command }: Args) -> Result<ExitCode> {
             Ok(ExitCode::SUCCESS)
         },
         Commands::Ask(arg) => {
-            commands::ask::ask(arg, &global)?;
+            commands::ask::ask(arg, &Project::load(&global)?)?;
             Ok(ExitCode::SUCCESS)
         },
-        Commands::Render => {
-            commands::render::render(&global)?;
+        Commands::Answers(args) => {
+            commands::answers::answers(args, &Project::load(&global)?)?;
             Ok(ExitCode::SUCCESS)
         },
-        Commands::Link => {
-            commands::link::link(&global)?;
+        Commands::Render { fail_fast } => {
+            let project = Project::load_with(&global, Layer::default().with_fail_fast(fail_fast))?;
+            commands::render::render(&project, &Filter::default(), project.settings.fail_fast)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Link { fail_fast } => {
+            let project = Project::load_with(&global, Layer::default().with_fail_fast(fail_fast))?;
+            commands::link::link(&project, &Filter::default(), project.settings.fail_fast)?;
             Ok(ExitCode::SUCCESS)
         },
         Commands::Completions { shell } => {
             shell.generate(&mut Args::command(), &mut std::io::stdout());
             Ok(ExitCode::SUCCESS)
         },
-        Commands::Install => {
-            commands::install::install(&global)?;
+        Commands::Install(args) => {
+            let flags = Layer::from_install_args(&args);
+            commands::install::install(&Project::load_with(&global, flags)?)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Watch(args) => {
+            commands::watch::watch(args, &global)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Check => {
+            commands::check::check(&global)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Config(args) => {
+            commands::config::config(args, &Project::load(&global)?)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::MigrateConfig(args) => {
+            commands::migrate::migrate_config(args, &global)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Pull(args) => {
+            commands::pull::pull(args, &global)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Commands::Commit(args) => {
+            commands::commit::commit(args, &Project::load(&global)?)?;
             Ok(ExitCode::SUCCESS)
         },
-        Commands::Pull => {
-            commands::pull::pull(&global)?;
+        Commands::Push => {
+            commands::push::push(&Project::load(&global)?)?;
             Ok(ExitCode::SUCCESS)
         },
         Commands::JsonSchema(args) => {
@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::{panic, thread};
+
+use anyhow::Result;
+
+/// Apply `f` to all items on a pool of worker threads, keeping the order of the items.
+pub fn map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(idx) else {
+                            return done;
+                        };
+                        done.push((idx, f(item)));
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let done = handle
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err));
+            for (idx, result) in done {
+                results[idx] = Some(result);
+            }
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.expect("Every item is processed by a worker"))
+        .collect()
+}
+
+/// Like [`map`], but with `fail_fast` no further items are started once one failed.
+///
+/// Items which were not started have no result.
+pub fn try_map<T: Sync, R: Send>(
+    items: &[T],
+    fail_fast: bool,
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Vec<Option<Result<R>>> {
+    let failed = AtomicBool::new(false);
+    map(items, |item| {
+        if failed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let result = f(item);
+        if fail_fast && result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+        Some(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_order() {
+        let items: Vec<usize> = (0..100).collect();
+        let doubled = map(&items, |&i| i * 2);
+        assert_eq!(doubled, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(map(&Vec::<usize>::new(), |&i| i).is_empty());
+    }
+
+    #[test]
+    fn test_try_map() {
+        let items: Vec<usize> = (0..100).collect();
+        let check = |&i: &usize| {
+            if i == 0 {
+                anyhow::bail!("zero");
+            }
+            thread::sleep(Duration::from_millis(1));
+            Ok(i)
+        };
+
+        let results = try_map(&items, false, check);
+        assert!(results.iter().all(Option::is_some));
+        assert_eq!(results.iter().flatten().filter(|r| r.is_err()).count(), 1);
+
+        // Workers stop picking up items after the first failure
+        let results = try_map(&items, true, check);
+        assert!(results.iter().any(Option::is_none));
+    }
+}
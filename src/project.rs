@@ -0,0 +1,195 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::args::GlobalArgs;
+use crate::config::Config;
+use crate::fragment::load_config;
+use crate::settings::{Layer, Settings};
+
+/// A dotfile repository, paths in its config are relative to its root
+#[derive(Debug)]
+pub struct Project {
+    /// The directory containing the dotfiles
+    pub root: PathBuf,
+
+    /// The config file
+    pub config_path: PathBuf,
+
+    pub config: Config,
+
+    /// All files the config was read from, see [`load_config`]
+    pub files: Vec<PathBuf>,
+
+    pub settings: Settings,
+}
+
+impl Project {
+    /// Load the project selected by the global arguments
+    pub fn load(global: &GlobalArgs) -> Result<Self> {
+        Self::load_with(global, Layer::default())
+    }
+
+    /// Load the project with settings given by command line flags
+    pub fn load_with(global: &GlobalArgs, flags: Layer) -> Result<Self> {
+        let (root, config_path) = Self::locate(global)?;
+        let (config, files) = load_config(&config_path, &root)?;
+        let settings = Settings::load(&config_path, flags)?;
+        Ok(Self {
+            root,
+            config_path,
+            config,
+            files,
+            settings,
+        })
+    }
+
+    /// The root directory and config file selected by the global arguments.
+    ///
+    /// Without `--root`, the root is the closest directory containing the config file,
+    /// starting from the current directory and going upwards.
+    pub fn locate(global: &GlobalArgs) -> Result<(PathBuf, PathBuf)> {
+        if let Some(root) = &global.root {
+            let config_path = find_config(root, &global.config_path)
+                .unwrap_or_else(|| root.join(&global.config_path));
+            return Ok((root.clone(), config_path));
+        }
+        if global.config_path.is_absolute() {
+            let root = global.config_path.parent().unwrap_or(Path::new("/"));
+            return Ok((root.to_path_buf(), global.config_path.clone()));
+        }
+
+        let cwd = env::current_dir().context("Failed to get the current directory")?;
+        match discover(&cwd, &global.config_path) {
+            Some(root) => {
+                let config_path = find_config(&root, &global.config_path)
+                    .unwrap_or_else(|| root.join(&global.config_path));
+                Ok((root, config_path))
+            },
+            None => bail!(
+                "Could not find '{}' in '{}' or any parent directory",
+                global.config_path.display(),
+                cwd.display()
+            ),
+        }
+    }
+
+    /// Resolve a path from the config, which is relative to the root
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn answer_file(&self) -> PathBuf {
+        self.resolve(&self.config.answer_file)
+    }
+
+    pub fn state_file(&self) -> PathBuf {
+        self.resolve(&self.config.state_file)
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        self.resolve(&self.config.log_dir)
+    }
+}
+
+/// Config file names tried in order when the default config file is not given explicitly
+const CONFIG_NAMES: [&str; 4] = ["sams.toml", "sams.yaml", "sams.yml", "sams.json"];
+
+/// The config file in `dir`. For the default `sams.toml`, a YAML or JSON config file with
+/// the same name is used as well.
+fn find_config(dir: &Path, config_path: &Path) -> Option<PathBuf> {
+    let names = if config_path == Path::new(CONFIG_NAMES[0]) {
+        &CONFIG_NAMES[..]
+    } else {
+        &[][..]
+    };
+    std::iter::once(config_path)
+        .chain(names.iter().map(Path::new))
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The closest directory from `cwd` upwards containing the config file, relative to `cwd`
+/// so paths in the output stay short.
+fn discover(cwd: &Path, config_path: &Path) -> Option<PathBuf> {
+    let levels = cwd
+        .ancestors()
+        .position(|dir| find_config(dir, config_path).is_some())?;
+    Some(match levels {
+        0 => PathBuf::from("."),
+        _ => (0..levels).map(|_| "..").collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_explicit_root() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("sams.toml"),
+            "answer_file = \"answers.toml\"",
+        )
+        .unwrap();
+
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+
+        assert_eq!(project.config_path, dir.path().join("sams.toml"));
+        assert_eq!(project.answer_file(), dir.path().join("answers.toml"));
+        assert_eq!(
+            project.resolve(Path::new("scripts")),
+            dir.path().join("scripts")
+        );
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("nvim/lua");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.path().join("sams.toml"), "").unwrap();
+
+        let config_path = Path::new("sams.toml");
+        assert_eq!(discover(dir.path(), config_path), Some(".".into()));
+        assert_eq!(discover(&nested, config_path), Some("../..".into()));
+        assert_eq!(discover(&nested, Path::new("missing.toml")), None);
+    }
+
+    #[test]
+    fn test_yaml_config_is_found() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("sams.yaml"), "answer_file: answers.json").unwrap();
+
+        let project = Project::load(&GlobalArgs {
+            config_path: "sams.toml".into(),
+            root: Some(dir.path().to_path_buf()),
+        })
+        .unwrap();
+
+        assert_eq!(project.config_path, dir.path().join("sams.yaml"));
+        assert_eq!(project.answer_file(), dir.path().join("answers.json"));
+    }
+
+    #[test]
+    fn test_absolute_config_path() {
+        let (root, config_path) = Project::locate(&GlobalArgs {
+            config_path: "/dotfiles/sams.toml".into(),
+            root: None,
+        })
+        .unwrap();
+
+        assert_eq!(root, PathBuf::from("/dotfiles"));
+        assert_eq!(config_path, PathBuf::from("/dotfiles/sams.toml"));
+    }
+}
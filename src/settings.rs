@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::args::InstallArgs;
+use crate::config::{deserialize, Config};
+use crate::format::Format;
+
+/// Settings given by one source.
+///
+/// Repo settings like `answer_file` describe the repository and are only given by the repo
+/// config, which is where [`Config`] reads them from. Personal settings like `jobs` are
+/// given by, in increasing precedence: the user config, `SAMS_CFG_*` environment variables
+/// and command line flags.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Layer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_file: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_suffix: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_suffix: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub respect_gitignore: Option<bool>,
+
+    /// Number of install tasks to run in parallel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+
+    /// Stream task output instead of collapsing it into the progress line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<bool>,
+
+    /// Number of output lines to show when a task fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tail: Option<usize>,
+
+    /// Stop rendering and linking at the first failing file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_fast: Option<bool>,
+
+    /// Milliseconds `watch` waits for further changes before re-rendering
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debounce: Option<u64>,
+}
+
+/// The settings which are about the user instead of the repository
+const PERSONAL: [&str; 5] = ["jobs", "verbose", "tail", "fail_fast", "debounce"];
+
+impl Layer {
+    /// The flags of the install arguments
+    pub fn from_install_args(args: &InstallArgs) -> Self {
+        Self {
+            jobs: args.jobs,
+            verbose: args.verbose.then_some(true),
+            tail: args.tail,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_fail_fast(self, fail_fast: bool) -> Self {
+        Self {
+            fail_fast: fail_fast.then_some(true),
+            ..self
+        }
+    }
+
+    fn defaults() -> Self {
+        let config = Config::default();
+        Self {
+            answer_file: Some(config.answer_file),
+            state_file: Some(config.state_file),
+            log_dir: Some(config.log_dir),
+            template_suffix: Some(config.template_suffix),
+            link_suffix: Some(config.link_suffix),
+            respect_gitignore: Some(config.respect_gitignore),
+            jobs: Some(1),
+            verbose: Some(false),
+            tail: Some(20),
+            fail_fast: Some(false),
+            debounce: Some(200),
+        }
+    }
+
+    /// The given settings by name
+    fn values(&self) -> Map<String, Value> {
+        match serde_json::to_value(self) {
+            Ok(Value::Object(values)) => values,
+            _ => Map::new(),
+        }
+    }
+}
+
+/// Where the effective value of a setting comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    User(PathBuf),
+    Repo(PathBuf),
+    Env(String),
+    Flag,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::User(path) => write!(f, "user config {}", path.display()),
+            Origin::Repo(path) => write!(f, "repo config {}", path.display()),
+            Origin::Env(var) => write!(f, "environment variable {}", var),
+            Origin::Flag => write!(f, "command line flag"),
+        }
+    }
+}
+
+/// The effective personal settings. The repo settings are part of the [`Config`] itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub jobs: usize,
+    pub verbose: bool,
+    pub tail: usize,
+    pub fail_fast: bool,
+    pub debounce: u64,
+
+    /// The effective value and origin of every setting by name
+    pub values: BTreeMap<String, (Value, Origin)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::resolve(vec![(Layer::defaults(), Origin::Default)])
+    }
+}
+
+impl Settings {
+    /// Resolve the settings from all sources. The repo settings are only collected to show
+    /// them with their origin, their values are those of the config read from `config_path`.
+    pub fn load(config_path: &Path, flags: Layer) -> Result<Self> {
+        let mut layers = vec![(Layer::defaults(), Origin::Default)];
+        if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+            layers.push((read_user_config(&path)?, Origin::User(path)));
+        }
+        layers.push((
+            repo_layer(config_path)?,
+            Origin::Repo(config_path.to_path_buf()),
+        ));
+        layers.extend(env_layers(env::vars_os())?);
+        layers.push((flags, Origin::Flag));
+        Ok(Self::resolve(layers))
+    }
+
+    /// Merge the layers, later ones taking precedence
+    fn resolve(layers: Vec<(Layer, Origin)>) -> Self {
+        let mut values = BTreeMap::new();
+        for (layer, origin) in layers {
+            for (name, value) in layer.values() {
+                values.insert(name, (value, origin.clone()));
+            }
+        }
+
+        let effective = effective_layer(&values);
+        Self {
+            jobs: effective.jobs.unwrap_or(1),
+            verbose: effective.verbose.unwrap_or_default(),
+            tail: effective.tail.unwrap_or_default(),
+            fail_fast: effective.fail_fast.unwrap_or_default(),
+            debounce: effective.debounce.unwrap_or_default(),
+            values,
+        }
+    }
+}
+
+/// The effective settings as a single layer
+fn effective_layer(values: &BTreeMap<String, (Value, Origin)>) -> Layer {
+    let values = values
+        .iter()
+        .map(|(name, (value, _))| (name.clone(), value.clone()))
+        .collect();
+    // Every value was serialized from a layer, so it deserializes again
+    serde_json::from_value(Value::Object(values)).unwrap_or_default()
+}
+
+/// `$XDG_CONFIG_HOME/sams/config.toml`, defaulting to `~/.config/sams/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("sams").join("config.toml"))
+}
+
+fn read_user_config(path: &Path) -> Result<Layer> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read user config file at '{}'", path.display()))?;
+    let layer: Layer = deserialize(&contents, Format::from_path(path))
+        .with_context(|| format!("Failed to parse user config file at '{}'", path.display()))?;
+    if let Some(name) = layer.values().keys().find(|name| !is_personal(name)) {
+        bail!(
+            "Setting '{}' in the user config file at '{}' can only be given in the repo config",
+            name,
+            path.display()
+        );
+    }
+    Ok(layer)
+}
+
+/// Whether a setting is about the user instead of the repository, only those can be given
+/// outside of the repo config
+pub fn is_personal(name: &str) -> bool {
+    PERSONAL.contains(&name)
+}
+
+/// The settings given in the repo config, which has already been validated
+fn repo_layer(config_path: &Path) -> Result<Layer> {
+    let contents = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file at '{}'", config_path.display()))?;
+    let config: Value = Format::from_path(config_path).parse(&contents)?;
+    let names = Layer::defaults().values();
+    let values: Map<String, Value> = match config {
+        Value::Object(config) => config
+            .into_iter()
+            .filter(|(name, _)| names.contains_key(name))
+            .collect(),
+        _ => Map::new(),
+    };
+    serde_json::from_value(Value::Object(values))
+        .with_context(|| format!("Failed to parse config file at '{}'", config_path.display()))
+}
+
+/// A layer for every `SAMS_CFG_<SETTING>` environment variable of a personal setting, like
+/// `SAMS_CFG_JOBS=4`. The prefix keeps them apart from `SAMS_ANSWER_<NAME>`.
+///
+/// Other variables are not decoded, as they may be set by anything and need not be UTF-8.
+fn env_layers(
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Result<Vec<(Layer, Origin)>> {
+    let mut layers = Vec::new();
+    for (var, raw) in vars {
+        if !var.as_encoded_bytes().starts_with(b"SAMS_CFG_") {
+            continue;
+        }
+        let (Some(var), Some(raw)) = (var.to_str(), raw.to_str()) else {
+            bail!(
+                "Environment variable {} is not valid UTF-8",
+                var.to_string_lossy()
+            );
+        };
+        let (var, raw) = (var.to_string(), raw.to_string());
+        let name = var["SAMS_CFG_".len()..].to_lowercase();
+        if !is_personal(&name) {
+            bail!(
+                "Unknown setting in environment variable {}, only {} can be given this way",
+                var,
+                PERSONAL.join(", ")
+            );
+        }
+
+        // Numbers and booleans are parsed, anything else is taken as a string
+        let parsed = serde_json::from_str(&raw).ok();
+        let layer = parsed
+            .into_iter()
+            .chain([Value::String(raw.clone())])
+            .find_map(|value| serde_json::from_value::<Layer>(json!({ &name: value })).ok());
+        match layer {
+            Some(layer) => layers.push((layer, Origin::Env(var))),
+            None => bail!("Invalid value for environment variable {}: '{}'", var, raw),
+        }
+    }
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    use super::*;
+
+    #[test]
+    fn test_layers_take_precedence_in_order() {
+        let user = PathBuf::from("/home/me/.config/sams/config.toml");
+        let repo = PathBuf::from("/dotfiles/sams.toml");
+        let env = env_layers([
+            ("SAMS_CFG_JOBS".into(), "4".into()),
+            ("SAMS_ANSWER_FILE".into(), "notes.txt".into()),
+            ("SAMS_ANSWER_EDITOR".into(), "nvim".into()),
+            ("HOME".into(), "/home/me".into()),
+        ])
+        .unwrap();
+        assert_eq!(env.len(), 1);
+
+        let mut layers = vec![
+            (Layer::defaults(), Origin::Default),
+            (
+                Layer {
+                    jobs: Some(2),
+                    tail: Some(50),
+                    ..Layer::default()
+                },
+                Origin::User(user.clone()),
+            ),
+            (
+                Layer {
+                    log_dir: Some(".logs".into()),
+                    ..Layer::default()
+                },
+                Origin::Repo(repo.clone()),
+            ),
+        ];
+        layers.extend(env);
+        layers.push((Layer::default().with_fail_fast(true), Origin::Flag));
+        let settings = Settings::resolve(layers);
+
+        assert_eq!(settings.jobs, 4);
+        assert_eq!(settings.tail, 50);
+        assert!(settings.fail_fast);
+        assert!(!settings.verbose);
+        assert_eq!(settings.debounce, 200);
+
+        let origin = |name: &str| settings.values[name].1.to_string();
+        assert_eq!(origin("jobs"), "environment variable SAMS_CFG_JOBS");
+        assert_eq!(origin("tail"), format!("user config {}", user.display()));
+        assert_eq!(origin("log_dir"), format!("repo config {}", repo.display()));
+        assert_eq!(origin("fail_fast"), "command line flag");
+        assert_eq!(origin("debounce"), "default");
+        assert_eq!(
+            settings.values["answer_file"].0,
+            json!(".sams-answers.toml")
+        );
+    }
+
+    #[test]
+    fn test_invalid_env_value() {
+        let err = env_layers([("SAMS_CFG_JOBS".into(), "many".into())]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value for environment variable SAMS_CFG_JOBS: 'many'"
+        );
+
+        let err = env_layers([("SAMS_CFG_LOG_DIR".into(), "/tmp".into())]).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Unknown setting in environment variable SAMS_CFG_LOG_DIR"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_non_utf8_env() {
+        // Unrelated variables may hold anything
+        env::set_var("SAMS_TEST_NOT_UTF8", OsStr::from_bytes(b"\xff"));
+        assert!(env_layers(env::vars_os()).is_ok());
+
+        let var = (
+            OsString::from("SAMS_CFG_JOBS"),
+            OsString::from_vec(b"\xff".to_vec()),
+        );
+        let err = env_layers([var]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment variable SAMS_CFG_JOBS is not valid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_user_config_only_gives_personal_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "jobs = 4\ndebounce = 50").unwrap();
+        let layer = read_user_config(&path).unwrap();
+        assert_eq!(layer.jobs, Some(4));
+        assert_eq!(layer.debounce, Some(50));
+
+        fs::write(&path, "jobs = 4\nanswer_file = \"answers.toml\"").unwrap();
+        let err = read_user_config(&path).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Setting 'answer_file' in the user config file"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_load_shows_repo_settings_with_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("sams.toml");
+        fs::write(&config_path, "log_dir = \".logs\"").unwrap();
+
+        let flags = Layer {
+            jobs: Some(3),
+            ..Layer::default()
+        };
+        let settings = Settings::load(&config_path, flags).unwrap();
+
+        assert_eq!(settings.jobs, 3);
+        assert_eq!(settings.values["log_dir"].0, json!(".logs"));
+        assert_eq!(settings.values["log_dir"].1, Origin::Repo(config_path));
+        assert_eq!(settings.values["state_file"].1, Origin::Default);
+    }
+}
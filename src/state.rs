@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic;
+
+/// Machine local record of what has already been done
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct State {
+    /// `<key>:<hash>` of each version of `run_policy = "once"` tasks which already ran
+    /// successfully, see [`State::once_entry`]
+    #[serde(default)]
+    pub once: BTreeSet<String>,
+
+    /// Content hash of the last successful run of each `run_policy = "onchange"` task
+    #[serde(default)]
+    pub onchange: BTreeMap<String, String>,
+
+    /// Hash of the template, the answers it refers to and the output of the last render of
+    /// each template, keyed by the root relative template path
+    #[serde(default)]
+    pub renders: BTreeMap<String, String>,
+
+    /// Root relative outputs of deleted templates, which are still generated files as long
+    /// as they exist
+    #[serde(default)]
+    pub stale: BTreeSet<String>,
+}
+
+impl State {
+    /// Load the state file, starting fresh if it does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse state file: {}", path.display()))
+    }
+
+    /// The entry of a task in [`State::once`]. Tasks with the same command are told apart by
+    /// their key.
+    pub fn once_entry(key: &str, hash: &str) -> String {
+        format!("{}:{}", key, hash)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize state")?;
+        atomic::write(path, content)
+            .with_context(|| format!("Failed to write state file: {}", path.display()))
+    }
+}
+
+/// Hex encoded SHA-256 of the given content
+pub fn content_hash(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+
+        assert_eq!(State::load(&path).unwrap(), State::default());
+
+        let mut state = State::default();
+        state.once.insert(content_hash(b"echo once"));
+        state
+            .onchange
+            .insert("fonts".into(), content_hash(b"fc-cache -f"));
+        state.save(&path).unwrap();
+
+        assert_eq!(State::load(&path).unwrap(), state);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(
+            content_hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
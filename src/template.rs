@@ -1,20 +1,318 @@
-use std::fs;
-use std::path::Path;
+use std::collections::{BTreeSet, HashSet};
 
 use anyhow::{Context, Result};
-use tera::{Context as TeraContext, Tera};
+use tera::ast::{Expr, ExprVal, FunctionCall, Node};
+use tera::{Context as TeraContext, ErrorKind, Template, Tera};
 
-pub fn render_template(from: &Path, to: &Path, context: &TeraContext) -> Result<()> {
-    let template = fs::read_to_string(from)
-        .with_context(|| format!("Failed to read template: {}", from.display()))?;
+/// The variables of the context whose name occurs in the template.
+///
+/// This over-approximates the variables the template uses, which is enough to tell
+/// whether a change of the answers can affect the output.
+pub fn referenced_context(template: &str, context: &TeraContext) -> serde_json::Value {
+    let words: HashSet<&str> = template
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .collect();
+    match context.clone().into_json() {
+        serde_json::Value::Object(map) => map
+            .into_iter()
+            .filter(|(name, _)| words.contains(name.as_str()))
+            .collect(),
+        value => value,
+    }
+}
+
+/// Parse a template on its own, to report syntax errors per file
+pub fn parse_template(name: &str, content: &str) -> Result<()> {
+    parse(name, content).map(|_| ())
+}
+
+/// The context variables a template uses, without those it defines itself.
+///
+/// Variables used with the `default` filter or in a `defined` test are left out, as the
+/// template handles them being missing.
+pub fn template_variables(name: &str, content: &str) -> Result<BTreeSet<String>> {
+    let template = parse(name, content)?;
+    let mut variables = Variables::default();
+    variables.nodes(&template.ast);
+    Ok(variables.used)
+}
+
+/// Parse a template on its own. `Template::new` and the AST are not part of the documented
+/// API of Tera, which is why its version is pinned to a minor release.
+fn parse(name: &str, content: &str) -> Result<Template> {
+    Template::new(name, None, content).map_err(|err| {
+        let location = match error_location(&err) {
+            Some((line, column)) => format!("{}:{}:{}", name, line, column),
+            None => name.to_string(),
+        };
+        anyhow::Error::new(err).context(format!("Failed to parse template: {}", location))
+    })
+}
+
+/// Line and column of a syntax error, which Tera only reports as part of the message
+fn error_location(err: &tera::Error) -> Option<(usize, usize)> {
+    let message = err.to_string();
+    let (_, rest) = message.split_once("--> ")?;
+    let (line, rest) = rest.split_once(':')?;
+    let column: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
 
-    let rendered = render_template_str(&template, context)?;
+/// Collects the variables used by a template AST
+#[derive(Default)]
+struct Variables {
+    used: BTreeSet<String>,
+    /// Variables defined by the template, like loop variables and `set`
+    defined: HashSet<String>,
+}
+
+impl Variables {
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::VariableBlock(_, expr) => self.expr(expr),
+            Node::Set(_, set) => {
+                self.expr(&set.value);
+                self.defined.insert(set.key.clone());
+            },
+            Node::FilterSection(_, section, _) => {
+                self.call(&section.filter);
+                self.nodes(&section.body);
+            },
+            Node::Block(_, block, _) => self.nodes(&block.body),
+            Node::If(condition, _) => {
+                for (_, expr, body) in &condition.conditions {
+                    self.expr(expr);
+                    self.nodes(body);
+                }
+                if let Some((_, body)) = &condition.otherwise {
+                    self.nodes(body);
+                }
+            },
+            Node::Forloop(_, forloop, _) => {
+                self.expr(&forloop.container);
+                let outer = self.defined.clone();
+                self.defined.extend(forloop.key.clone());
+                self.defined.insert(forloop.value.clone());
+                self.defined.insert("loop".to_string());
+                self.nodes(&forloop.body);
+                if let Some(body) = &forloop.empty_body {
+                    self.nodes(body);
+                }
+                self.defined = outer;
+            },
+            Node::MacroDefinition(_, definition, _) => {
+                let outer = self.defined.clone();
+                self.defined.extend(definition.args.keys().cloned());
+                for default in definition.args.values().flatten() {
+                    self.expr(default);
+                }
+                self.nodes(&definition.body);
+                self.defined = outer;
+            },
+            _ => {},
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.val {
+            ExprVal::Ident(_) if expr.has_default_filter() => {},
+            value => self.value(value),
+        }
+        for filter in &expr.filters {
+            self.call(filter);
+        }
+    }
+
+    fn call(&mut self, call: &FunctionCall) {
+        for arg in call.args.values() {
+            self.expr(arg);
+        }
+    }
+
+    fn value(&mut self, value: &ExprVal) {
+        match value {
+            ExprVal::Ident(ident) => self.ident(ident),
+            ExprVal::Math(math) => {
+                self.expr(&math.lhs);
+                self.expr(&math.rhs);
+            },
+            ExprVal::Logic(logic) => {
+                self.expr(&logic.lhs);
+                self.expr(&logic.rhs);
+            },
+            ExprVal::In(within) => {
+                self.expr(&within.lhs);
+                self.expr(&within.rhs);
+            },
+            ExprVal::Test(test) => {
+                if test.name != "defined" && test.name != "undefined" {
+                    self.ident(&test.ident);
+                }
+                for arg in &test.args {
+                    self.expr(arg);
+                }
+            },
+            ExprVal::MacroCall(call) => {
+                for arg in call.args.values() {
+                    self.expr(arg);
+                }
+            },
+            ExprVal::FunctionCall(call) => self.call(call),
+            ExprVal::Array(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+            },
+            ExprVal::StringConcat(concat) => {
+                for value in &concat.values {
+                    self.value(value);
+                }
+            },
+            ExprVal::String(_) | ExprVal::Int(_) | ExprVal::Float(_) | ExprVal::Bool(_) => {},
+        }
+    }
+
+    /// Record the variable at the root of an identifier like `user.name` or `items[0]`
+    fn ident(&mut self, ident: &str) {
+        let root = ident.split(['.', '[']).next().unwrap_or(ident);
+        if !root.starts_with("__tera") && !self.defined.contains(root) {
+            self.used.insert(root.to_string());
+        }
+    }
+}
 
-    fs::write(to, rendered).with_context(|| format!("Failed to write output: {}", to.display()))?;
+/// Compile templates into a single Tera instance in which they can include each other.
+///
+/// Templates which can not be compiled with the others, like one extending a missing
+/// template, are left out and returned by name with their error.
+pub fn compile_templates<'a>(
+    templates: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(Tera, Vec<(String, anyhow::Error)>)> {
+    let mut templates: Vec<(&str, &str)> = templates.into_iter().collect();
+    let mut failed = Vec::new();
+    loop {
+        let mut tera = Tera::default();
+        // Escape all templates, like `render_template_str` does
+        tera.autoescape_on(vec![""]);
+        let err = match tera.add_raw_templates(templates.iter().copied()) {
+            Ok(()) => return Ok((tera, failed)),
+            Err(err) => err,
+        };
 
-    Ok(())
+        let failing = failing_template(&err)
+            .and_then(|name| templates.iter().position(|(other, _)| *other == name));
+        let Some(index) = failing else {
+            return Err(anyhow::Error::new(err).context("Failed to compile templates"));
+        };
+        let (name, _) = templates.remove(index);
+        let err = anyhow::Error::new(err).context(format!("Failed to compile template: {}", name));
+        failed.push((name.to_string(), err));
+    }
+}
+
+/// The template an error of compiling several templates together is about
+fn failing_template(err: &tera::Error) -> Option<&str> {
+    match &err.kind {
+        ErrorKind::MissingParent { current, .. } => Some(current),
+        ErrorKind::CircularExtend { tpl, .. } => Some(tpl),
+        // Missing macro files are only named in the message
+        ErrorKind::Msg(message) => message
+            .strip_prefix("Template `")?
+            .split_once('`')
+            .map(|(name, _)| name),
+        _ => None,
+    }
 }
 
 pub fn render_template_str(template: &str, context: &TeraContext) -> Result<String> {
     Tera::one_off(template, context, true).with_context(|| "Failed to render template")
 }
+
+/// Render a template without HTML escaping, for commands and other non markup text
+pub fn render_raw_str(template: &str, context: &TeraContext) -> Result<String> {
+    Tera::one_off(template, context, false).with_context(|| "Failed to render template")
+}
+
+/// Evaluate a Tera expression like `os == "linux" and editor == "nvim"` to a boolean
+pub fn eval_condition(expr: &str, context: &TeraContext) -> Result<bool> {
+    let template = format!("{{% if {expr} %}}true{{% endif %}}");
+    let rendered = render_template_str(&template, context)
+        .with_context(|| format!("Failed to evaluate condition: {expr}"))?;
+    Ok(rendered == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referenced_context() {
+        let mut ctx = TeraContext::new();
+        ctx.insert("os", "linux");
+        ctx.insert("editor", "nvim");
+        ctx.insert("editor_theme", "dark");
+
+        let referenced =
+            referenced_context("{% if os == \"linux\" %}{{ editor }}{% endif %}", &ctx);
+        assert_eq!(
+            referenced,
+            serde_json::json!({"os": "linux", "editor": "nvim"})
+        );
+    }
+
+    #[test]
+    fn test_parse_template_reports_location() {
+        assert!(parse_template("ok.tpl", "{{ key }}").is_ok());
+
+        let err = parse_template("bad.tpl", "line\n{{ invalid syntax }}").unwrap_err();
+        assert_eq!(err.to_string(), "Failed to parse template: bad.tpl:2:12");
+    }
+
+    #[test]
+    fn test_template_variables() {
+        let template = r#"
+{% set greeting = "Hello" %}
+{{ greeting }} {{ user.name | upper }}
+{% for plugin in plugins %}{{ loop.index }} {{ plugin }}{% endfor %}
+{% if os == "linux" and theme is defined %}{{ theme }}{% endif %}
+{{ editor | default(value=fallback) }}
+"#;
+        let variables = template_variables("vars.tpl", template).unwrap();
+        assert_eq!(
+            variables.into_iter().collect::<Vec<_>>(),
+            ["fallback", "os", "plugins", "theme", "user"]
+        );
+    }
+
+    #[test]
+    fn test_eval_condition() {
+        let mut ctx = TeraContext::new();
+        ctx.insert("os", "linux");
+        ctx.insert("editor", "nvim");
+
+        assert!(eval_condition(r#"os == "linux""#, &ctx).unwrap());
+        assert!(!eval_condition(r#"os == "linux" and editor == "vim""#, &ctx).unwrap());
+        assert!(!eval_condition("missing_var", &ctx).unwrap());
+        assert!(eval_condition("os ==", &ctx).is_err());
+    }
+
+    #[test]
+    fn test_compile_templates_leaves_out_broken_templates() {
+        let (tera, failed) = compile_templates([
+            ("good", "{{ 1 }}"),
+            ("orphan", r#"{% extends "missing" %}"#),
+            ("macros", r#"{% import "missing" as m %}"#),
+            ("child", r#"{% extends "orphan" %}"#),
+        ])
+        .unwrap();
+        let names: BTreeSet<&str> = failed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, BTreeSet::from(["orphan", "macros", "child"]));
+        assert_eq!(tera.get_template_names().collect::<Vec<_>>(), ["good"]);
+    }
+}
@@ -1,10 +1,13 @@
 use std::path::Path;
 
 use anyhow::Result;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use ignore::overrides::{Override, OverrideBuilder};
-use ignore::{Walk, WalkBuilder};
+use ignore::{DirEntry, WalkBuilder};
 
 use crate::config::Config;
+use crate::filter::Filter;
+use crate::generated::in_git_repo;
 
 pub struct WalkOptions {
     /// Filter files by extension
@@ -18,6 +21,9 @@ pub struct WalkOptions {
 
     /// Ignore gitignore files
     pub respect_gitignore: bool,
+
+    /// Only yield files selected by this filter
+    pub filter: Filter,
 }
 
 impl Default for WalkOptions {
@@ -27,6 +33,7 @@ impl Default for WalkOptions {
             excludes: Vec::new(),
             ignore_hidden: false,
             respect_gitignore: true,
+            filter: Filter::default(),
         }
     }
 }
@@ -38,6 +45,7 @@ impl WalkOptions {
             excludes: cfg.exclude.clone(),
             ignore_hidden: true,
             respect_gitignore: cfg.respect_gitignore,
+            filter: Filter::default(),
         }
     }
 
@@ -46,27 +54,93 @@ impl WalkOptions {
         self
     }
 
-    pub fn walk(self, root: &Path) -> Result<Walk> {
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Walk all matching files below the root, directories are not yielded.
+    pub fn walk(
+        self,
+        root: &Path,
+    ) -> Result<impl Iterator<Item = Result<DirEntry, ignore::Error>>> {
         let mut builder = WalkBuilder::new(root);
 
         builder.standard_filters(self.respect_gitignore);
         builder.hidden(self.ignore_hidden);
-        builder.filter_entry(|entry| entry.file_type().is_some_and(|ft| ft.is_file()));
 
         let overrides = self.build_glob(root)?;
         builder.overrides(overrides);
 
-        if let Some(ext) = self.filter_extension {
-            builder.filter_entry(move |entry| {
-                entry
-                    .path()
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .is_some_and(|e| e == ext)
-            });
-        };
-
-        Ok(builder.build())
+        // Directories have to pass the filter as well, otherwise they are not descended into.
+        let extension = self.filter_extension;
+        builder.filter_entry(move |entry| {
+            let Some(file_type) = entry.file_type() else {
+                return false;
+            };
+            file_type.is_dir()
+                || (file_type.is_file()
+                    && extension.as_deref().is_none_or(|ext| {
+                        entry
+                            .path()
+                            .extension()
+                            .and_then(|e| e.to_str())
+                            .is_some_and(|e| e == ext)
+                    }))
+        });
+
+        let filter = self.filter;
+        let root = root.to_path_buf();
+        Ok(builder.build().filter(move |entry| match entry {
+            Ok(entry) => {
+                let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+                entry.file_type().is_some_and(|ft| ft.is_file()) && filter.matches_file(relative)
+            },
+            Err(_) => true,
+        }))
+    }
+
+    /// Whether walking the root would skip the root relative path because of the excludes,
+    /// hidden files or ignore files, for paths found otherwise, like by a file watcher
+    pub fn skips(&self, root: &Path, relative: &Path) -> Result<bool> {
+        let overrides = self.build_glob(root)?;
+        let respect_ignore_files = self.respect_gitignore && in_git_repo(root);
+        let mut ignore_files = Vec::new();
+        if respect_ignore_files {
+            let mut exclude = GitignoreBuilder::new(root);
+            exclude.add(root.join(".git/info/exclude"));
+            ignore_files.push(Gitignore::global().0);
+            ignore_files.extend(exclude.build().ok());
+        }
+        let mut dir = root.to_path_buf();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            if respect_ignore_files {
+                for name in [".gitignore", ".ignore"] {
+                    let (ignore, _) = Gitignore::new(dir.join(name));
+                    ignore_files.push(ignore);
+                }
+            }
+            let path = dir.join(component);
+            let is_dir = components.peek().is_some() || path.is_dir();
+
+            let hidden = component.as_os_str().to_string_lossy().starts_with('.');
+            if (self.ignore_hidden && hidden) || overrides.matched(&path, is_dir).is_ignore() {
+                return Ok(true);
+            }
+            // Deeper ignore files take precedence
+            for ignore in ignore_files.iter().rev() {
+                let matched = ignore.matched(&path, is_dir);
+                if matched.is_ignore() {
+                    return Ok(true);
+                }
+                if matched.is_whitelist() {
+                    break;
+                }
+            }
+            dir = path;
+        }
+        Ok(false)
     }
 
     fn build_glob(&self, root: &Path) -> Result<Override> {